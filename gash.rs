@@ -13,26 +13,57 @@
 #[ warn(non_camel_case_types) ];
 extern mod extra;
 
+use std::os;
 use shell::shell::Shell;
-use helpers::helpers::get_cmdline_from_args;
+use helpers::helpers::{get_mode_from_args, exec_program, use_environment_locale, Interactive,
+                       Command, Script, Launch};
+use terminal::terminal;
 
 #[ path = "shell/shell.rs" ]     mod shell;
 #[ path = "shell/helpers.rs" ]   mod helpers;
 #[ path="functional.rs"]         mod functional;
 #[ path="shell/shellprocess.rs"] mod shellprocess;
 #[ path="shell/parser.rs"]       mod parser;
+#[ path="shell/errors.rs"]       mod errors;
+#[ path="shell/signals.rs"]      mod signals;
+#[ path="shell/terminal.rs"]     mod terminal;
+#[ path="shell/jobs.rs"]         mod jobs;
+#[ path="shell/commands.rs"]     mod commands;
+#[ path="shell/usage.rs"]        mod usage;
+#[ path="shell/limits.rs"]       mod limits;
+#[ path="shell/options.rs"]      mod options;
+#[ path="shell/printf.rs"]       mod printf;
+#[ path="shell/conditions.rs"]   mod conditions;
 
 fn main() {
-    let opt_cmd_line = get_cmdline_from_args();
-    
-    match opt_cmd_line {
-        Some(cmd_line) => {
+    use_environment_locale();
+    match get_mode_from_args() {
+        Command(cmd_line) => {
             let mut shell = Shell::new("");
-            shell.run_cmdline(cmd_line);
+            shell.run_line(cmd_line);
+            shell.leave();
+            os::set_exit_status(shell.last_status);
         }
-        None => {
+        Script(path) => {
+            let mut shell = Shell::new("");
+            shell.run_script(&Path::new(path));
+            shell.leave();
+            os::set_exit_status(shell.last_status);
+        }
+        Interactive => {
             let mut shell = Shell::new("gash > ");
             shell.start();
+            shell.leave();
+            os::set_exit_status(shell.last_status);
+        }
+        Launch(pgid, foreground, program, argv) => {
+            match pgid {
+                Some(pgid) => { terminal::join(pgid, foreground); }
+                None       => { }
+            }
+            let e = exec_program(program, argv);
+            e.report();
+            os::set_exit_status(e.status());
         }
     }
 }
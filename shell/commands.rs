@@ -0,0 +1,206 @@
+#[allow(dead_code)]
+pub mod commands {
+    // Finding programs. A command name without a slash is looked for in
+    // each directory of $PATH in turn, and the first executable file found
+    // is the one that runs. Lookups are remembered in a hash table, which is
+    // thrown away whenever $PATH changes.
+    use std::hashmap::HashMap;
+    use std::cmp::min;
+    use std::libc::{c_int, c_char};
+    use std::c_str::ToCStr;
+    use std::io::io_error;
+    use std::io::fs::readdir;
+
+    static X_OK : c_int = 1;
+
+    extern {
+        fn access(path: *c_char, mode: c_int) -> c_int;
+    }
+
+    // True if path is a file we may execute.
+    fn is_executable(path: &Path) -> bool {
+        path.is_file() && path.with_c_str(|p| unsafe { access(p, X_OK) }) == 0
+    }
+
+    // Find the program a command name refers to. A name with a slash in it
+    // is a path already, and isn't looked up. An empty entry in $PATH means
+    // the current directory.
+    pub fn find_program(name: &str, path_var: &str) -> Option<Path> {
+        if name.contains_char('/') {
+            let path = Path::new(name);
+            return if is_executable(&path) { Some(path) } else { None };
+        }
+        for dir in path_var.split(':') {
+            let dir = if dir == "" { "." } else { dir };
+            let path = Path::new(dir).join(name);
+            if is_executable(&path) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    // Every program name on $PATH, for suggesting one when a command
+    // can't be found.
+    pub fn program_names(path_var: &str) -> ~[~str] {
+        let mut names = ~[];
+        for dir in path_var.split(':') {
+            let dir = Path::new(if dir == "" { "." } else { dir });
+            // Directories that can't be read have nothing to suggest.
+            let entries = io_error::cond.trap(|_| { }).inside(|| readdir(&dir));
+            for path in entries.iter() {
+                match path.filename_str() {
+                    Some(name) if is_executable(path) => { names.push(name.to_owned()); }
+                    _ => { }
+                }
+            }
+        }
+        names
+    }
+
+    // The number of single character insertions, deletions and
+    // substitutions it takes to turn a into b (Levenshtein distance).
+    pub fn edit_distance(a: &str, b: &str) -> uint {
+        let b: ~[char] = b.chars().collect();
+        let mut row: ~[uint] = range(0, b.len() + 1).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut next = ~[i + 1];
+            for (j, &cb) in b.iter().enumerate() {
+                let substitute = row[j] + if ca == cb { 0 } else { 1 };
+                let insert = next[j] + 1;
+                let delete = row[j + 1] + 1;
+                next.push(min(substitute, min(insert, delete)));
+            }
+            row = next;
+        }
+        row[b.len()]
+    }
+
+    // A remembered lookup, and how many times it has been used.
+    struct Entry {
+        path : Path,
+        hits : uint,
+    }
+
+    pub struct CommandTable {
+        entries  : HashMap<~str, Entry>,
+        // The $PATH the entries were found with.
+        path_var : ~str,
+    }
+
+    impl CommandTable {
+        pub fn new() -> CommandTable {
+            CommandTable {
+                entries: HashMap::new(),
+                path_var: ~"",
+            }
+        }
+
+        // Find a program, from the table if it's there. Names with a slash
+        // are never remembered.
+        pub fn find(&mut self, name: &str, path_var: &str) -> Option<Path> {
+            if self.path_var.as_slice() != path_var {
+                self.clear();
+                self.path_var = path_var.to_owned();
+            }
+            if name.contains_char('/') {
+                return find_program(name, path_var);
+            }
+            match self.entries.find_mut(&name.to_owned()) {
+                Some(entry) => {
+                    // A remembered program that has gone away is looked
+                    // up again.
+                    if is_executable(&entry.path) {
+                        entry.hits += 1;
+                        return Some(entry.path.clone());
+                    }
+                }
+                None => {
+                }
+            }
+            match find_program(name, path_var) {
+                Some(path) => {
+                    self.entries.insert(name.to_owned(), Entry { path: path.clone(), hits: 1 });
+                    Some(path)
+                }
+                None => {
+                    self.entries.remove(&name.to_owned());
+                    None
+                }
+            }
+        }
+
+        // Look a program up and remember it without counting it as used,
+        // as `hash name` does. Returns false if it can't be found.
+        pub fn add(&mut self, name: &str, path_var: &str) -> bool {
+            match self.find(name, path_var) {
+                Some(_) => {
+                    match self.entries.find_mut(&name.to_owned()) {
+                        Some(entry) => { entry.hits -= 1; }
+                        None        => { }
+                    }
+                    true
+                }
+                None => {
+                    false
+                }
+            }
+        }
+
+        // Forget one program. Returns false if it wasn't remembered.
+        pub fn forget(&mut self, name: &str) -> bool {
+            self.entries.remove(&name.to_owned())
+        }
+
+        pub fn clear(&mut self) {
+            self.entries = HashMap::new();
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries.len() == 0
+        }
+
+        // The remembered programs as (hits, path), sorted by name.
+        pub fn list(&self) -> ~[(uint, ~str)] {
+            let mut names: ~[&~str] = self.entries.keys().collect();
+            names.sort();
+            names.iter().map(|name| {
+                let entry = self.entries.get(*name);
+                (entry.hits, format!("{}", entry.path.display()))
+            }).collect()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::edit_distance;
+
+        #[test]
+        fn identical_names_are_no_distance_apart() {
+            assert_eq!(edit_distance("", ""), 0);
+            assert_eq!(edit_distance("grep", "grep"), 0);
+        }
+
+        #[test]
+        fn each_edit_counts_once() {
+            assert_eq!(edit_distance("gerp", "grep"), 2);
+            assert_eq!(edit_distance("sl", "ls"), 2);
+            assert_eq!(edit_distance("lss", "ls"), 1);
+            assert_eq!(edit_distance("l", "ls"), 1);
+            assert_eq!(edit_distance("cst", "cat"), 1);
+            assert_eq!(edit_distance("kitten", "sitting"), 3);
+        }
+
+        #[test]
+        fn empty_names_are_their_length_away() {
+            assert_eq!(edit_distance("", "make"), 4);
+            assert_eq!(edit_distance("make", ""), 4);
+        }
+
+        #[test]
+        fn distance_counts_characters_not_bytes() {
+            assert_eq!(edit_distance("caf\u00e9", "cafe"), 1);
+            assert_eq!(edit_distance("\u00e9t\u00e9", "ete"), 2);
+        }
+    }
+}
@@ -0,0 +1,850 @@
+#[allow(dead_code)]
+pub mod conditions {
+    // Conditional expressions, for the test and [ builtins and the [[ ... ]]
+    // keyword. test gets its arguments as already split words, and follows
+    // POSIX. [[ gets its text unexpanded, so that it can expand each word
+    // itself without splitting it, tell quoted text from patterns, and see
+    // && and || as its own operators. Both share the file, string and
+    // integer tests.
+    use std::libc::{c_char, c_int, size_t};
+    use std::libc::types::os::arch::posix01::stat;
+    use std::libc::types::os::arch::posix88::mode_t;
+    use std::libc::consts::os::posix88::{S_IFMT, S_IFIFO, S_IFCHR, S_IFDIR, S_IFREG,
+                                         R_OK, W_OK, X_OK};
+    use std::libc::funcs::posix88::unistd::{access, isatty, geteuid, getegid};
+    use std::libc::funcs::posix88::stat_::stat;
+    use std::libc::funcs::posix01::stat_::lstat;
+    use std::unstable::intrinsics;
+    use std::c_str::ToCStr;
+    use std::str;
+    use helpers::helpers::expand_vars;
+
+    // regex_t from <regex.h>, left opaque: it is bigger than any C
+    // library's, and only re_nsub is read, from the word it is in. glibc
+    // has six pointer-sized fields before it; the BSDs and bionic one int.
+    struct Regex {
+        words : [uint, ..32],
+    }
+
+    #[cfg(target_os = "linux")]
+    static RE_NSUB : uint = 6;
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "freebsd")]
+    #[cfg(target_os = "android")]
+    static RE_NSUB : uint = 1;
+
+    // regmatch_t: glibc's offsets are ints, the others' are off_t.
+    #[cfg(target_os = "linux")]
+    struct RegMatch {
+        rm_so : c_int,
+        rm_eo : c_int,
+    }
+
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "freebsd")]
+    #[cfg(target_os = "android")]
+    struct RegMatch {
+        rm_so : ::std::libc::types::os::arch::posix88::off_t,
+        rm_eo : ::std::libc::types::os::arch::posix88::off_t,
+    }
+
+    static REG_EXTENDED : c_int = 1;
+
+    // The file types and mode bits std::libc has no names for, and block
+    // devices, as <sys/stat.h> has them.
+    static S_IFBLK  : mode_t = 0x6000;
+    static S_IFLNK  : mode_t = 0xa000;
+    static S_IFSOCK : mode_t = 0xc000;
+    static S_ISUID  : mode_t = 0x800;
+    static S_ISGID  : mode_t = 0x400;
+    static S_ISVTX  : mode_t = 0x200;
+
+    extern {
+        fn regcomp(regex: *mut Regex, pattern: *c_char, flags: c_int) -> c_int;
+        fn regexec(regex: *Regex, text: *c_char, nmatch: size_t, matches: *mut RegMatch,
+                   flags: c_int) -> c_int;
+        fn regfree(regex: *mut Regex);
+    }
+
+    // stat (or with follow false, lstat) a path.
+    fn file_stat(path: &str, follow: bool) -> Option<stat> {
+        if path.len() == 0 {
+            return None;
+        }
+        let mut buf: stat = unsafe { intrinsics::init() };
+        let result = path.with_c_str(|p| unsafe {
+            if follow { stat(p, &mut buf) } else { lstat(p, &mut buf) }
+        });
+        if result == 0 { Some(buf) } else { None }
+    }
+
+    fn file_type(path: &str, kind: mode_t) -> bool {
+        file_stat(path, true).map_default(false, |st| st.st_mode & (S_IFMT as mode_t) == kind)
+    }
+
+    fn file_mode(path: &str, bit: mode_t) -> bool {
+        file_stat(path, true).map_default(false, |st| st.st_mode & bit != 0)
+    }
+
+    fn can_access(path: &str, mode: c_int) -> bool {
+        path.len() > 0 && path.with_c_str(|p| unsafe { access(p, mode) }) == 0
+    }
+
+    // The operators that take one argument.
+    static UNARY: &'static [&'static str] = &[
+        "-b", "-c", "-d", "-e", "-f", "-g", "-G", "-h", "-k", "-L", "-n", "-N", "-O", "-p",
+        "-r", "-s", "-S", "-t", "-u", "-w", "-x", "-z",
+    ];
+
+    // The operators between two arguments, besides the pattern matching
+    // ones [[ has.
+    static BINARY: &'static [&'static str] = &[
+        "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+    ];
+
+    fn is_unary(op: &str) -> bool {
+        UNARY.iter().any(|unary| *unary == op)
+    }
+
+    fn is_binary(op: &str) -> bool {
+        BINARY.iter().any(|binary| *binary == op)
+    }
+
+    // Apply a unary operator.
+    fn unary(op: &str, arg: &str) -> bool {
+        match op {
+            "-b" => { file_type(arg, S_IFBLK) }
+            "-c" => { file_type(arg, S_IFCHR as mode_t) }
+            "-d" => { file_type(arg, S_IFDIR as mode_t) }
+            "-e" => { file_stat(arg, true).is_some() }
+            "-f" => { file_type(arg, S_IFREG as mode_t) }
+            "-g" => { file_mode(arg, S_ISGID) }
+            "-G" => {
+                file_stat(arg, true).map_default(false, |st| st.st_gid == unsafe { getegid() })
+            }
+            "-h" | "-L" => {
+                file_stat(arg, false).map_default(false, |st| st.st_mode & (S_IFMT as mode_t) == S_IFLNK)
+            }
+            "-k" => { file_mode(arg, S_ISVTX) }
+            "-n" => { arg.len() > 0 }
+            "-N" => {
+                file_stat(arg, true).map_default(false, |st| {
+                    (st.st_mtime, st.st_mtime_nsec) > (st.st_atime, st.st_atime_nsec)
+                })
+            }
+            "-O" => {
+                file_stat(arg, true).map_default(false, |st| st.st_uid == unsafe { geteuid() })
+            }
+            "-p" => { file_type(arg, S_IFIFO as mode_t) }
+            "-r" => { can_access(arg, R_OK) }
+            "-s" => { file_stat(arg, true).map_default(false, |st| st.st_size > 0) }
+            "-S" => { file_type(arg, S_IFSOCK) }
+            "-t" => {
+                from_str::<c_int>(arg.trim()).map_default(false, |fd| unsafe { isatty(fd) } == 1)
+            }
+            "-u" => { file_mode(arg, S_ISUID) }
+            "-w" => { can_access(arg, W_OK) }
+            "-x" => { can_access(arg, X_OK) }
+            "-z" => { arg.len() == 0 }
+            _    => { false }
+        }
+    }
+
+    fn integer(arg: &str) -> Result<i64, ~str> {
+        match from_str::<i64>(arg.trim()) {
+            Some(n) => { Ok(n) }
+            None    => { Err(format!("{:s}: integer expression expected", arg)) }
+        }
+    }
+
+    // Modification time, to the nanosecond.
+    fn modified(path: &str) -> Option<(i64, i64)> {
+        file_stat(path, true).map(|st| (st.st_mtime as i64, st.st_mtime_nsec as i64))
+    }
+
+    fn compare(left: &str, right: &str, f: |i64, i64| -> bool) -> Result<bool, ~str> {
+        match (integer(left), integer(right)) {
+            (Ok(a), Ok(b)) => { Ok(f(a, b)) }
+            (Err(e), _)    => { Err(e) }
+            (_, Err(e))    => { Err(e) }
+        }
+    }
+
+    // Apply a binary operator. = and == compare strings exactly here.
+    fn binary(left: &str, op: &str, right: &str) -> Result<bool, ~str> {
+        match op {
+            "=" | "==" => { Ok(left == right) }
+            "!="  => { Ok(left != right) }
+            "<"   => { Ok(left < right) }
+            ">"   => { Ok(left > right) }
+            "-eq" => { compare(left, right, |a, b| a == b) }
+            "-ne" => { compare(left, right, |a, b| a != b) }
+            "-lt" => { compare(left, right, |a, b| a < b) }
+            "-le" => { compare(left, right, |a, b| a <= b) }
+            "-gt" => { compare(left, right, |a, b| a > b) }
+            "-ge" => { compare(left, right, |a, b| a >= b) }
+            // A file that exists is newer than one that doesn't.
+            "-nt" => {
+                Ok(match (modified(left), modified(right)) {
+                    (Some(a), Some(b)) => { a > b }
+                    (Some(_), None)    => { true }
+                    _                  => { false }
+                })
+            }
+            "-ot" => {
+                Ok(match (modified(left), modified(right)) {
+                    (Some(a), Some(b)) => { a < b }
+                    (None, Some(_))    => { true }
+                    _                  => { false }
+                })
+            }
+            "-ef" => {
+                Ok(match (file_stat(left, true), file_stat(right, true)) {
+                    (Some(a), Some(b)) => { a.st_dev == b.st_dev && a.st_ino == b.st_ino }
+                    _                  => { false }
+                })
+            }
+            _ => {
+                Err(format!("{:s}: binary operator expected", op))
+            }
+        }
+    }
+
+    // Evaluate the arguments of test (without the ] of [). Errors are
+    // messages to follow "test: ".
+    pub fn test(args: &[~str]) -> Result<bool, ~str> {
+        let args: ~[&str] = args.iter().map(|arg| arg.as_slice()).collect();
+        let mut parser = TestParser { args: args, next: 0 };
+        if parser.args.len() == 0 {
+            return Ok(false);
+        }
+        let result = parser.or();
+        if parser.next < parser.args.len() {
+            return Err(format!("{:s}: unexpected argument", parser.args[parser.next]));
+        }
+        result
+    }
+
+    // A recursive descent parser for test's arguments. -o binds more
+    // loosely than -a, which binds more loosely than !. Where a word could
+    // be an operator or an operand, the reading POSIX gives for up to four
+    // arguments wins: a binary operator in second place comes first.
+    struct TestParser<'a> {
+        args : ~[&'a str],
+        next : uint,
+    }
+
+    impl<'a> TestParser<'a> {
+        fn peek(&self, ahead: uint) -> Option<&'a str> {
+            if self.next + ahead < self.args.len() {
+                Some(self.args[self.next + ahead])
+            }
+            else {
+                None
+            }
+        }
+
+        fn or(&mut self) -> Result<bool, ~str> {
+            let mut value = match self.and() {
+                Ok(value) => { value }
+                Err(e)    => { return Err(e); }
+            };
+            while self.peek(0) == Some("-o") {
+                self.next += 1;
+                match self.and() {
+                    Ok(right) => { value = value || right; }
+                    Err(e)    => { return Err(e); }
+                }
+            }
+            Ok(value)
+        }
+
+        fn and(&mut self) -> Result<bool, ~str> {
+            let mut value = match self.not() {
+                Ok(value) => { value }
+                Err(e)    => { return Err(e); }
+            };
+            while self.peek(0) == Some("-a") {
+                self.next += 1;
+                match self.not() {
+                    Ok(right) => { value = value && right; }
+                    Err(e)    => { return Err(e); }
+                }
+            }
+            Ok(value)
+        }
+
+        fn not(&mut self) -> Result<bool, ~str> {
+            let rest = self.args.len() - self.next;
+            // A lone "!" is just a string, and "! = x" a comparison.
+            if self.peek(0) == Some("!") && rest > 1
+                    && !(rest == 3 && is_binary(self.peek(1).unwrap())) {
+                self.next += 1;
+                return self.not().map(|value| !value);
+            }
+            self.primary()
+        }
+
+        fn primary(&mut self) -> Result<bool, ~str> {
+            let first = match self.peek(0) {
+                Some(first) => { first }
+                None        => { return Err(~"argument expected"); }
+            };
+            match (self.peek(1), self.peek(2)) {
+                (Some(op), Some(right)) if is_binary(op) => {
+                    self.next += 3;
+                    return binary(first, op, right);
+                }
+                _ => { }
+            }
+            if first == "(" && self.peek(1).is_some() {
+                self.next += 1;
+                let value = self.or();
+                if self.peek(0) != Some(")") {
+                    return Err(~"`)' expected");
+                }
+                self.next += 1;
+                return value;
+            }
+            if is_unary(first) {
+                match self.peek(1) {
+                    Some(arg) => {
+                        self.next += 2;
+                        return Ok(unary(first, arg));
+                    }
+                    None => { }
+                }
+            }
+            self.next += 1;
+            Ok(first.len() > 0)
+        }
+    }
+
+    // A word of a [[ ... ]] expression, expanded: its plain value, and its
+    // value as a glob pattern and as a regular expression, in which the
+    // parts that were quoted match only themselves.
+    struct Word {
+        value   : ~str,
+        pattern : ~str,
+        regex   : ~str,
+    }
+
+    enum CondToken {
+        WordToken(Word),
+        // &&, ||, !, (, ), < or >, unquoted.
+        OpToken(~str),
+    }
+
+    // Split the text of a [[ ... ]] into words and operators, expanding
+    // variables but not splitting what they expand to.
+    fn cond_tokens(text: &str, lookup: |&str| -> Option<~str>) -> Result<~[CondToken], ~str> {
+        let chars: ~[char] = text.chars().collect();
+        let mut tokens = ~[];
+        let mut word = Word { value: ~"", pattern: ~"", regex: ~"" };
+        let mut in_word = false;
+        // Parentheses open in a regular expression.
+        let mut depth = 0;
+        let mut i = 0;
+        // Adds text to the word, quoted or not.
+        fn add(word: &mut Word, text: &str, quoted: bool) {
+            word.value.push_str(text);
+            if quoted {
+                for c in text.chars() {
+                    if "*?[]\\".contains_char(c) {
+                        word.pattern.push_char('\\');
+                    }
+                    word.pattern.push_char(c);
+                    if "^$.[]|()?*+{}\\".contains_char(c) {
+                        word.regex.push_char('\\');
+                    }
+                    word.regex.push_char(c);
+                }
+            }
+            else {
+                word.pattern.push_str(text);
+                word.regex.push_str(text);
+            }
+        }
+        while i < chars.len() {
+            let c = chars[i];
+            let regex = follows_regex_op(tokens);
+            if ends_word(c, regex, depth) {
+                if in_word {
+                    tokens.push(WordToken(word));
+                    word = Word { value: ~"", pattern: ~"", regex: ~"" };
+                    in_word = false;
+                    depth = 0;
+                }
+                if c.is_whitespace() {
+                    i += 1;
+                    continue;
+                }
+                let doubled = i + 1 < chars.len() && chars[i + 1] == c;
+                let op = match c {
+                    '&' | '|' if doubled => { str::from_chars([c, c]) }
+                    '&' | '|' => { return Err(format!("syntax error near `{}'", c)); }
+                    _ => { c.to_str() }
+                };
+                i += op.len();
+                tokens.push(OpToken(op));
+                continue;
+            }
+            in_word = true;
+            match c {
+                '\'' => {
+                    let close = match chars.slice_from(i + 1).position_elem(&'\'') {
+                        Some(close) => { i + 1 + close }
+                        None        => { return Err(~"unexpected EOF while looking for `''"); }
+                    };
+                    add(&mut word, str::from_chars(chars.slice(i + 1, close)), true);
+                    i = close + 1;
+                }
+                '"' => {
+                    let mut close = i + 1;
+                    while close < chars.len() && chars[close] != '"' {
+                        close += if chars[close] == '\\' { 2 } else { 1 };
+                    }
+                    if close >= chars.len() {
+                        return Err(~"unexpected EOF while looking for `\"'");
+                    }
+                    let inner = str::from_chars(chars.slice(i + 1, close))
+                        .replace("\\\"", "\"").replace("\\\\", "\\");
+                    add(&mut word, expand_vars(inner, |name| lookup(name)), true);
+                    i = close + 1;
+                }
+                '\\' if i + 1 < chars.len() => {
+                    add(&mut word, chars[i + 1].to_str(), true);
+                    i += 2;
+                }
+                _ => {
+                    // Run up to the next quote, escape or break.
+                    let mut end = i;
+                    while end < chars.len() && !ends_word(chars[end], regex, depth)
+                            && !"'\"\\".contains_char(chars[end]) {
+                        if regex && chars[end] == '(' {
+                            depth += 1;
+                        }
+                        else if regex && chars[end] == ')' && depth > 0 {
+                            depth -= 1;
+                        }
+                        end += 1;
+                    }
+                    let end = if end == i { i + 1 } else { end };
+                    let text = str::from_chars(chars.slice(i, end));
+                    add(&mut word, expand_vars(text, |name| lookup(name)), false);
+                    i = end;
+                }
+            }
+        }
+        if in_word {
+            tokens.push(WordToken(word));
+        }
+        Ok(tokens)
+    }
+
+    // Whether the word being read is the regular expression after =~.
+    fn follows_regex_op(tokens: &[CondToken]) -> bool {
+        if tokens.len() == 0 {
+            return false;
+        }
+        match tokens[tokens.len() - 1] {
+            WordToken(ref word) => { word.value == ~"=~" }
+            OpToken(_)          => { false }
+        }
+    }
+
+    // Whether c, unquoted, ends a word. In a regular expression parentheses
+    // and | are part of the word, and so is whitespace inside parentheses.
+    fn ends_word(c: char, regex: bool, depth: uint) -> bool {
+        if regex {
+            depth == 0 && (c.is_whitespace() || "&<>".contains_char(c))
+        }
+        else {
+            c.is_whitespace() || "&|()<>".contains_char(c)
+        }
+    }
+
+    // What a [[ ... ]] came to: whether it was true, and for a =~ match
+    // that succeeded, the text that matched and what each parenthesised
+    // part of the regular expression matched.
+    pub struct Outcome {
+        result  : bool,
+        matches : Option<~[~str]>,
+    }
+
+    // Evaluate the text between [[ and ]]. Errors are messages to follow
+    // "[[: " or the like.
+    pub fn condition(text: &str, lookup: |&str| -> Option<~str>) -> Result<Outcome, ~str> {
+        let tokens = match cond_tokens(text, lookup) {
+            Ok(tokens) => { tokens }
+            Err(e)     => { return Err(e); }
+        };
+        if tokens.len() == 0 {
+            return Err(~"syntax error: expression expected");
+        }
+        let mut parser = CondParser { tokens: tokens, next: 0, matches: None };
+        let result = parser.or(true);
+        if parser.next < parser.tokens.len() {
+            return Err(format!("syntax error near `{:s}'", parser.describe(parser.next)));
+        }
+        result.map(|result| Outcome { result: result, matches: parser.matches.take() })
+    }
+
+    // A recursive descent parser for [[ ... ]], evaluating as it goes. When
+    // live is false the expression is only being parsed, because && or ||
+    // has already settled the result.
+    struct CondParser {
+        tokens  : ~[CondToken],
+        next    : uint,
+        matches : Option<~[~str]>,
+    }
+
+    impl CondParser {
+        fn describe(&self, i: uint) -> ~str {
+            match self.tokens[i] {
+                WordToken(ref word) => { word.value.clone() }
+                OpToken(ref op)     => { op.clone() }
+            }
+        }
+
+        fn is_op(&self, op: &str) -> bool {
+            if self.next >= self.tokens.len() {
+                return false;
+            }
+            match self.tokens[self.next] {
+                OpToken(ref token) => { token.as_slice() == op }
+                _                  => { false }
+            }
+        }
+
+        // The word at next, taking it.
+        fn word(&mut self) -> Result<(~str, ~str, ~str), ~str> {
+            if self.next >= self.tokens.len() {
+                return Err(~"syntax error: unexpected end of expression");
+            }
+            self.next += 1;
+            match self.tokens[self.next - 1] {
+                WordToken(ref word) => {
+                    Ok((word.value.clone(), word.pattern.clone(), word.regex.clone()))
+                }
+                OpToken(ref op) => {
+                    Err(format!("syntax error near `{:s}'", *op))
+                }
+            }
+        }
+
+        fn or(&mut self, live: bool) -> Result<bool, ~str> {
+            let mut value = match self.and(live) {
+                Ok(value) => { value }
+                Err(e)    => { return Err(e); }
+            };
+            while self.is_op("||") {
+                self.next += 1;
+                match self.and(live && !value) {
+                    Ok(right) => { value = value || right; }
+                    Err(e)    => { return Err(e); }
+                }
+            }
+            Ok(value)
+        }
+
+        fn and(&mut self, live: bool) -> Result<bool, ~str> {
+            let mut value = match self.not(live) {
+                Ok(value) => { value }
+                Err(e)    => { return Err(e); }
+            };
+            while self.is_op("&&") {
+                self.next += 1;
+                match self.not(live && value) {
+                    Ok(right) => { value = value && right; }
+                    Err(e)    => { return Err(e); }
+                }
+            }
+            Ok(value)
+        }
+
+        fn not(&mut self, live: bool) -> Result<bool, ~str> {
+            if self.is_op("!") {
+                self.next += 1;
+                return self.not(live).map(|value| !value);
+            }
+            if self.is_op("(") {
+                self.next += 1;
+                let value = self.or(live);
+                if !self.is_op(")") {
+                    return Err(~"syntax error: `)' expected");
+                }
+                self.next += 1;
+                return value;
+            }
+            self.primary(live)
+        }
+
+        fn primary(&mut self, live: bool) -> Result<bool, ~str> {
+            let (first, _, _) = match self.word() {
+                Ok(word) => { word }
+                Err(e)   => { return Err(e); }
+            };
+            // A binary operator: a word, or an unquoted < or >.
+            let op = if self.is_op("<") || self.is_op(">") {
+                Some(self.describe(self.next))
+            }
+            else if self.next < self.tokens.len() {
+                match self.tokens[self.next] {
+                    WordToken(ref word) if is_binary(word.value) || word.value == ~"=~" => {
+                        Some(word.value.clone())
+                    }
+                    _ => { None }
+                }
+            }
+            else {
+                None
+            };
+            match op {
+                Some(op) => {
+                    self.next += 1;
+                    let (value, pattern, regex) = match self.word() {
+                        Ok(word) => { word }
+                        Err(e)   => { return Err(e); }
+                    };
+                    if !live {
+                        return Ok(false);
+                    }
+                    return match op.as_slice() {
+                        "=" | "==" => { Ok(glob_match(pattern, first)) }
+                        "!="       => { Ok(!glob_match(pattern, first)) }
+                        "=~"       => { self.regex_match(regex, first) }
+                        _          => { binary(first, op, value) }
+                    };
+                }
+                None => { }
+            }
+            if is_unary(first) && self.next < self.tokens.len() {
+                let (arg, _, _) = match self.word() {
+                    Ok(word) => { word }
+                    Err(e)   => { return Err(e); }
+                };
+                return Ok(live && unary(first, arg));
+            }
+            Ok(first.len() > 0)
+        }
+
+        // Match text against an extended regular expression, keeping what
+        // matched for BASH_REMATCH.
+        fn regex_match(&mut self, regex: &str, text: &str) -> Result<bool, ~str> {
+            let mut compiled = Regex { words: [0, ..32] };
+            let status = regex.with_c_str(|p| unsafe { regcomp(&mut compiled, p, REG_EXTENDED) });
+            if status != 0 {
+                return Err(format!("{:s}: invalid regular expression", regex));
+            }
+            let count = compiled.words[RE_NSUB] + 1;
+            let mut found = ~[];
+            for _ in range(0, count) {
+                found.push(RegMatch { rm_so: -1, rm_eo: -1 });
+            }
+            let status = text.with_c_str(|p| unsafe {
+                regexec(&compiled, p, count as size_t, found.as_mut_ptr(), 0)
+            });
+            unsafe {
+                regfree(&mut compiled);
+            }
+            if status != 0 {
+                self.matches = Some(~[]);
+                return Ok(false);
+            }
+            // Offsets are in bytes. Outside a UTF-8 locale the regex library
+            // can end a match inside a character, and such a part is left
+            // empty rather than cut.
+            let bytes = text.as_bytes();
+            self.matches = Some(found.iter().map(|m| {
+                let (start, end) = (m.rm_so as uint, m.rm_eo as uint);
+                if m.rm_so < 0 || start > end || end > bytes.len() {
+                    ~""
+                }
+                else {
+                    str::from_utf8_opt(bytes.slice(start, end)).map_default(~"", |part| {
+                        part.to_owned()
+                    })
+                }
+            }).collect());
+            Ok(true)
+        }
+    }
+
+    // Match text against a glob pattern: * matches any run of characters,
+    // ? any one, [...] any one listed (ranges like a-z; ! or ^ first
+    // negates), and a backslash makes the next character match itself.
+    pub fn glob_match(pattern: &str, text: &str) -> bool {
+        let pattern: ~[char] = pattern.chars().collect();
+        let text: ~[char] = text.chars().collect();
+        glob_from(pattern, 0, text, 0)
+    }
+
+    fn glob_from(pattern: &[char], p: uint, text: &[char], t: uint) -> bool {
+        let mut p = p;
+        let mut t = t;
+        while p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    while p < pattern.len() && pattern[p] == '*' {
+                        p += 1;
+                    }
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    for start in range(t, text.len() + 1) {
+                        if glob_from(pattern, p, text, start) {
+                            return true;
+                        }
+                    }
+                    return false;
+                }
+                '?' => {
+                    if t == text.len() {
+                        return false;
+                    }
+                }
+                '[' => {
+                    if t == text.len() {
+                        return false;
+                    }
+                    match bracket(pattern, p, text[t]) {
+                        Some((matched, end)) => {
+                            if !matched {
+                                return false;
+                            }
+                            p = end;
+                            t += 1;
+                            continue;
+                        }
+                        // An unclosed [ is an ordinary character.
+                        None => {
+                            if text[t] != '[' {
+                                return false;
+                            }
+                        }
+                    }
+                }
+                '\\' if p + 1 < pattern.len() => {
+                    p += 1;
+                    if t == text.len() || text[t] != pattern[p] {
+                        return false;
+                    }
+                }
+                c => {
+                    if t == text.len() || text[t] != c {
+                        return false;
+                    }
+                }
+            }
+            p += 1;
+            t += 1;
+        }
+        t == text.len()
+    }
+
+    // Match c against the bracket expression opening at pattern[open].
+    // Returns whether it matched and where the expression ends, or None if
+    // it isn't closed.
+    fn bracket(pattern: &[char], open: uint, c: char) -> Option<(bool, uint)> {
+        let mut i = open + 1;
+        let negate = i < pattern.len() && (pattern[i] == '!' || pattern[i] == '^');
+        if negate {
+            i += 1;
+        }
+        let mut matched = false;
+        let mut first = true;
+        while i < pattern.len() {
+            let mut low = pattern[i];
+            if low == ']' && !first {
+                return Some((matched != negate, i + 1));
+            }
+            first = false;
+            if low == '\\' && i + 1 < pattern.len() {
+                i += 1;
+                low = pattern[i];
+            }
+            let mut high = low;
+            if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+                high = pattern[i + 2];
+                i += 2;
+            }
+            if low <= c && c <= high {
+                matched = true;
+            }
+            i += 1;
+        }
+        None
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{test, condition};
+        use helpers::helpers::use_environment_locale;
+        use std::os;
+
+        fn run_test(args: &[&str]) -> Result<bool, ~str> {
+            let args: ~[~str] = args.iter().map(|arg| arg.to_owned()).collect();
+            test(args)
+        }
+
+        fn cond(text: &str) -> (bool, Option<~[~str]>) {
+            let outcome = condition(text, |name| {
+                if name == "re" { Some(~"^(a+)(b*)$") } else { None }
+            });
+            match outcome {
+                Ok(outcome) => { (outcome.result, outcome.matches) }
+                Err(e)      => { fail!("[[ {:s} ]]: {:s}", text, e) }
+            }
+        }
+
+        fn holds(text: &str) -> bool {
+            let (result, _) = cond(text);
+            result
+        }
+
+        #[test]
+        fn test_follows_posix_for_up_to_four_arguments() {
+            assert_eq!(run_test(&[]), Ok(false));
+            assert_eq!(run_test(&["x"]), Ok(true));
+            assert_eq!(run_test(&[""]), Ok(false));
+            assert_eq!(run_test(&["-z"]), Ok(true));
+            assert_eq!(run_test(&["!", ""]), Ok(true));
+            assert_eq!(run_test(&["-n", ""]), Ok(false));
+            assert_eq!(run_test(&["a", "=", "a"]), Ok(true));
+            assert_eq!(run_test(&["!", "=", "!"]), Ok(true));
+            assert_eq!(run_test(&["!", "-z", "x"]), Ok(true));
+            assert_eq!(run_test(&["(", "", ")"]), Ok(false));
+            assert_eq!(run_test(&["-a", "=", "-o"]), Ok(false));
+            assert_eq!(run_test(&["!", "a", "=", "b"]), Ok(true));
+            assert_eq!(run_test(&["(", "-n", "x", ")"]), Ok(true));
+            assert_eq!(run_test(&["2", "-lt", "10"]), Ok(true));
+            assert!(run_test(&["1", "-eq", "one"]).is_err());
+        }
+
+        #[test]
+        fn quoted_right_side_of_double_equals_is_not_a_pattern() {
+            assert!(holds("abc == a*"));
+            assert!(!holds("abc == 'a*'"));
+            assert!(holds("a* == \"a*\""));
+            assert!(!holds("abc == a\\*"));
+            assert!(!holds("abc != [ab]?c"));
+            assert!(!holds("abc == \"[ab]\"?c"));
+        }
+
+        #[test]
+        fn regex_match_captures_groups() {
+            assert_eq!(cond("abc123 =~ ([a-z]+)([0-9]+)"),
+                       (true, Some(~[~"abc123", ~"abc", ~"123"])));
+            assert_eq!(cond("aab =~ $re"), (true, Some(~[~"aab", ~"aa", ~"b"])));
+            assert_eq!(cond("x =~ (a)|(x)"), (true, Some(~[~"x", ~"", ~"x"])));
+            assert_eq!(cond("a.c =~ \".\""), (true, Some(~[~"."])));
+            assert_eq!(cond("abc =~ \".\""), (false, Some(~[])));
+            // gash takes its locale from the environment at startup.
+            os::setenv("LC_ALL", "C.UTF-8");
+            use_environment_locale();
+            assert_eq!(cond("\u00e9t\u00e9 =~ t(.)"), (true, Some(~[~"t\u00e9", ~"\u00e9"])));
+            assert_eq!(cond("\u00e9 =~ (.)"), (true, Some(~[~"\u00e9", ~"\u00e9"])));
+        }
+    }
+}
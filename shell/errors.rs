@@ -0,0 +1,71 @@
+pub mod errors {
+    use std::io;
+    use std::io::IoError;
+    use helpers::helpers::print_err;
+
+    // Things that can go wrong while running a command line. Each one is
+    // reported as a message, and the command line finishes with the matching
+    // exit status instead of taking the shell down with it.
+    pub enum ShellError {
+        // The program couldn't be found.
+        NotFound(~str),
+        // The program was found, but couldn't be executed.
+        NotExecutable(~str),
+        // A file in a redirection couldn't be opened: (path, reason).
+        RedirectFailed(~str, ~str),
+        // The program couldn't be started for some other reason:
+        // (program, reason).
+        SpawnFailed(~str, ~str),
+    }
+
+    impl ShellError {
+        // Classify the error raised while trying to start program.
+        pub fn from_io(program: &str, error: Option<IoError>) -> ShellError {
+            match error {
+                Some(e) => {
+                    match e.kind {
+                        io::FileNotFound     => { NotFound(program.to_owned()) }
+                        io::PermissionDenied => { NotExecutable(program.to_owned()) }
+                        _ => { SpawnFailed(program.to_owned(), e.desc.to_owned()) }
+                    }
+                }
+                None => {
+                    SpawnFailed(program.to_owned(), ~"unknown error")
+                }
+            }
+        }
+
+        // The exit status of a command line that failed this way.
+        pub fn status(&self) -> int {
+            match *self {
+                NotFound(_)          => 127,
+                NotExecutable(_)     => 126,
+                RedirectFailed(_, _) => 1,
+                SpawnFailed(_, _)    => 1,
+            }
+        }
+
+        pub fn report(&self) {
+            print_err(self.to_str());
+        }
+    }
+
+    impl ToStr for ShellError {
+        fn to_str(&self) -> ~str {
+            match *self {
+                NotFound(ref program) => {
+                    format!("gash: {:s}: command not found", *program)
+                }
+                NotExecutable(ref program) => {
+                    format!("gash: {:s}: permission denied", *program)
+                }
+                RedirectFailed(ref path, ref reason) => {
+                    format!("gash: {:s}: {:s}", *path, *reason)
+                }
+                SpawnFailed(ref program, ref reason) => {
+                    format!("gash: {:s}: {:s}", *program, *reason)
+                }
+            }
+        }
+    }
+}
@@ -3,87 +3,517 @@ extern mod extra;
 #[allow(dead_code)]
 pub mod helpers {
     use extra::getopts;
-    use std::io::buffered::BufferedReader;
-    use std::io::{Open, Truncate, Read, Write};
-    use std::io::fs::File;
+    use std::io::stderr;
+    use std::io::io_error;
+    use std::io::process::{ProcessExit, ExitStatus, ExitSignal};
     use std::os;
-    use std::run::Process;
-    // Ugly, ugly, word-splitting parser for individual commands. Gets the job
-    // done, though.
+    use std::str;
+    use std::char;
+    use std::run::{Process, ProcessOptions};
+    use std::libc;
+    use std::libc::{c_int, c_char};
+    use std::libc::types::os::arch::posix88::pid_t;
+    use std::libc::consts::os::posix88::{ENOENT, EACCES, EINTR};
+    use std::c_str::{CString, ToCStr};
+    use std::ptr;
+    use std::util::replace;
+    use errors::errors::{ShellError, RedirectFailed, NotFound, NotExecutable, SpawnFailed};
+
+    // Print a message to stderr.
+    pub fn print_err(msg: &str) {
+        stderr().write_line(msg);
+    }
+
+    // Remove comments from text: a '#' at the start of a word, outside of
+    // quotes, starts a comment that runs to the end of its line. A '#'
+    // inside a word, like a#b, is left alone.
+    pub fn strip_comments(text: &str) -> ~str {
+        let mut out = ~"";
+        let mut quote: Option<char> = None;
+        let mut in_comment = false;
+        let mut escaped = false;
+        let mut prev = ' ';
+        for c in text.chars() {
+            if in_comment {
+                if c == '\n' {
+                    in_comment = false;
+                    out.push_char(c);
+                }
+                prev = c;
+                continue;
+            }
+            if escaped {
+                escaped = false;
+            }
+            else if c == '\\' && quote != Some('\'') {
+                escaped = true;
+            }
+            else if quote.is_some() {
+                if quote == Some(c) {
+                    quote = None;
+                }
+            }
+            else if c == '\'' || c == '"' {
+                quote = Some(c);
+            }
+            else if c == '#' && (prev.is_whitespace() || ";&|()<>".contains_char(prev)) {
+                in_comment = true;
+                continue;
+            }
+            out.push_char(c);
+            prev = c;
+        }
+        out
+    }
+
+    // Split a command into words the way the shell reads them: whitespace
+    // separates words, and quotes and backslashes keep it (and anything
+    // else) inside one. Single quotes take everything up to the next one
+    // as it is; inside double quotes a backslash only escapes \, ", $ and
+    // `. The quotes are removed, and a word can be made of quoted and
+    // unquoted parts, like a"b c"d. Works on characters rather than bytes,
+    // so multi-byte UTF-8 in arguments is never split.
     pub fn split_words(words : &str) -> ~[~str] {
+        let chars: ~[char] = words.chars().collect();
         let mut splits = ~[];
-        let mut lastword = 0;
-        let mut quoted = false;
-        for i in range(0, words.len()) {
-            if !quoted {
-                if i == 0 {
-                    if words.char_at(i) == '"' {
-                        quoted = true;
-                        lastword = i+1;
+        let mut word = ~"";
+        // Whether a word has started, which "" does without adding to it.
+        let mut in_word = false;
+        let mut quote: Option<char> = None;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match quote {
+                Some('\'') => {
+                    if c == '\'' {
+                        quote = None;
+                    }
+                    else {
+                        word.push_char(c);
                     }
                 }
-                else {
-                    if words.char_at(i) == '"' && words.char_at(i-1) != '\\' {
-                        quoted = true;
-                        lastword = i+1;
+                Some(_) => {
+                    if c == '"' {
+                        quote = None;
+                    }
+                    else if c == '\\' && i + 1 < chars.len()
+                            && "\\\"$`".contains_char(chars[i + 1]) {
+                        word.push_char(chars[i + 1]);
+                        i += 1;
+                    }
+                    else {
+                        word.push_char(c);
+                    }
+                }
+                None if c.is_whitespace() => {
+                    if in_word {
+                        splits.push(replace(&mut word, ~""));
+                        in_word = false;
+                    }
+                }
+                None => {
+                    in_word = true;
+                    if c == '\'' || c == '"' {
+                        quote = Some(c);
+                    }
+                    else if c == '\\' && i + 1 < chars.len() {
+                        word.push_char(chars[i + 1]);
+                        i += 1;
+                    }
+                    else {
+                        word.push_char(c);
+                    }
+                }
+            }
+            i += 1;
+        }
+        if in_word {
+            splits.push(word);
+        }
+        splits
+    }
+
+    // The variable reference starting with the '$' at chars[i]: its name,
+    // and the index just past it. The special parameters $?, $- and $$ go
+    // by their one character names.
+    fn variable_at(chars: &[char], i: uint) -> Option<(~str, uint)> {
+        if i + 1 == chars.len() {
+            return None;
+        }
+        let mut name = ~"";
+        let mut j = i + 1;
+        if chars[j] == '{' {
+            j += 1;
+            while j < chars.len() && chars[j] != '}' {
+                name.push_char(chars[j]);
+                j += 1;
+            }
+            j += 1;
+        }
+        else if "?-$".contains_char(chars[j]) {
+            name.push_char(chars[j]);
+            j += 1;
+        }
+        else {
+            while j < chars.len()
+                    && (chars[j].is_alphanumeric() || chars[j] == '_') {
+                name.push_char(chars[j]);
+                j += 1;
+            }
+        }
+        if name.len() == 0 { None } else { Some((name, j)) }
+    }
+
+    // Expand $NAME and ${NAME} references in text using lookup. Unset
+    // variables expand to nothing, and a '$' that doesn't start a name is
+    // left alone. Quotes mean nothing here: this is for text that isn't a
+    // command, like $PS4, or a word whose quoting the caller has dealt
+    // with.
+    pub fn expand_vars(line: &str, lookup: |&str| -> Option<~str>) -> ~str {
+        let chars: ~[char] = line.chars().collect();
+        let mut out = ~"";
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] != '$' {
+                out.push_char(chars[i]);
+                i += 1;
+                continue;
+            }
+            match variable_at(chars, i) {
+                Some((name, end)) => {
+                    match lookup(name) {
+                        Some(value) => { out.push_str(value); }
+                        None => { }
                     }
-                    else if words.char_at(i) == ' ' && words.char_at(i-1) != '\\' {
-                        let word = words.slice(lastword, i).to_owned();
-                        if word != ~"" {
-                            splits.push(word);
+                    i = end;
+                }
+                None => {
+                    out.push_char('$');
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    // Expand the variable references in a command line that is still to
+    // be parsed, as the shell does: not inside single quotes or after a
+    // backslash. Values are quoted so that they are read back as they are,
+    // never as operators, quotes or more references. Outside double quotes
+    // a value is split into words at whitespace, and an empty one goes.
+    pub fn expand_words(line: &str, lookup: |&str| -> Option<~str>) -> ~str {
+        let chars: ~[char] = line.chars().collect();
+        let mut out = ~"";
+        let mut quote: Option<char> = None;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if quote == Some('\'') {
+                if c == '\'' {
+                    quote = None;
+                }
+                out.push_char(c);
+                i += 1;
+                continue;
+            }
+            if c == '\\' && i + 1 < chars.len() {
+                out.push_char(c);
+                out.push_char(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            if c == '"' {
+                quote = if quote.is_none() { Some(c) } else { None };
+            }
+            else if c == '\'' && quote.is_none() {
+                quote = Some(c);
+            }
+            let reference = if c == '$' { variable_at(chars, i) } else { None };
+            match reference {
+                Some((name, end)) => {
+                    let value = lookup(name).unwrap_or(~"");
+                    if quote.is_some() {
+                        for c in value.chars() {
+                            if "\\\"$`".contains_char(c) {
+                                out.push_char('\\');
+                            }
+                            out.push_char(c);
                         }
-                        lastword = i+1;
                     }
+                    else {
+                        let fields: ~[~str] = value.words().map(|field| {
+                            format!("'{:s}'", field.replace("'", "'\\''"))
+                        }).collect();
+                        out.push_str(fields.connect(" "));
+                    }
+                    i = end;
                 }
+                None => {
+                    out.push_char(c);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    // Input that isn't UTF-8 still has to reach programs as it was typed.
+    // Each byte that isn't part of a UTF-8 character is carried through
+    // the shell's strings as the character RAW_BYTES + byte, from a private
+    // use area, and turned back into that byte when it leaves the shell.
+    static RAW_BYTES : u32 = 0x10ff00;
+
+    // Bytes read by the shell, as one of its strings.
+    pub fn decode_bytes(bytes: &[u8]) -> ~str {
+        let mut text = ~"";
+        let mut i = 0;
+        while i < bytes.len() {
+            let lead = bytes[i];
+            let len = match lead {
+                0x00..0x7f => { 1 }
+                0xc2..0xdf => { 2 }
+                0xe0..0xef => { 3 }
+                0xf0..0xf4 => { 4 }
+                _          => { 0 }
+            };
+            let character = if len > 0 && i + len <= bytes.len() {
+                str::from_utf8_opt(bytes.slice(i, i + len))
             }
             else {
-                if words.char_at(i) == '"' {
-                    splits.push(words.slice(lastword, i).to_owned());
-                    lastword = i+1;
-                    quoted = false;
+                None
+            };
+            match character {
+                Some(character) => {
+                    text.push_str(character);
+                    i += len;
+                }
+                None => {
+                    text.push_char(char::from_u32(RAW_BYTES + lead as u32).unwrap());
+                    i += 1;
                 }
             }
         }
-        if lastword != words.len() {
-            splits.push(words.slice_from(lastword).to_owned());
+        text
+    }
+
+    // The bytes a string of the shell's stands for.
+    pub fn encode_bytes(text: &str) -> ~[u8] {
+        let mut bytes = ~[];
+        for c in text.chars() {
+            if is_raw_byte(c) {
+                bytes.push((c as u32 - RAW_BYTES) as u8);
+            }
+            else {
+                bytes.push_all(c.to_str().as_bytes());
+            }
         }
-        splits.iter().map(|x| x.replace("\\n", "\n")).collect()
+        bytes
     }
 
-    pub fn input_redirect(mut process: ~Process, path: &Path) -> ~Process {
-        let file = File::open_mode(path, Open, Read)
-            .expect(format!("ERR: Failed opening input file"));
-        let file_buffer = &mut BufferedReader::new(file);
-        process.input().write(file_buffer.read_to_end());
-        process
+    // True if text has bytes in it that aren't UTF-8.
+    pub fn has_raw_bytes(text: &str) -> bool {
+        text.chars().any(|c| is_raw_byte(c))
     }
 
-    pub fn write_output_to_file(output : ~[u8],
-                            path : &Path) {
-        let mut file = File::open_mode(path,
-                                        Truncate,  
-                                        Write)
-            .expect(format!("ERR: Failed opening output"));
-        file.write(output);
+    fn is_raw_byte(c: char) -> bool {
+        c as u32 >= RAW_BYTES + 0x80 && c as u32 <= RAW_BYTES + 0xff
     }
 
-    pub fn output_redirect(mut process : ~Process, path : &Path) -> ~Process {
-        let output = process.finish_with_output();
-        if output.status.success() {
-            write_output_to_file(output.output, path);
+    // Spawn a process, turning a failure to start it into a ShellError.
+    pub fn spawn_process(program: &str, args: &[~str], options: ProcessOptions)
+            -> Result<Process, ShellError> {
+        let mut options = Some(options);
+        let mut error = None;
+        let process = io_error::cond.trap(|e| { error = Some(e); }).inside(|| {
+            Process::new(program, args, options.take_unwrap())
+        });
+        match process {
+            Some(process) => { Ok(process) }
+            None          => { Err(ShellError::from_io(program, error)) }
         }
-        process
     }
 
-    pub fn pipe_redirect(mut left: ~Process, mut right: ~Process) -> ~Process {
-        right.input().write(left.finish_with_output().output);
-        left.close_outputs();
-        right
+    // The program to run for a subshell: gash itself, via -c. The runtime
+    // knows the directory the running executable is in, which works
+    // however gash was started; the file name is the one it was run as.
+    pub fn self_exe() -> ~str {
+        let args = os::args();
+        let name = Path::new(args[0].as_slice());
+        match (os::self_exe_path(), name.filename_str()) {
+            (Some(dir), Some(file)) => { format!("{}", dir.join(file).display()) }
+            _                       => { args[0].clone() }
+        }
+    }
+
+    // The exit status of a finished process, as $? would report it.
+    pub fn exit_status(exit: ProcessExit) -> int {
+        match exit {
+            ExitStatus(status) => { status }
+            ExitSignal(signal) => { 128 + signal }
+        }
+    }
+
+    // Replace the shell with program, run with argv (whose first element
+    // is its name), as the bytes they stand for. Only returns if that
+    // fails, saying why.
+    pub fn exec_program(program: &str, argv: &[~str]) -> ShellError {
+        let args: ~[CString] = argv.iter().map(|arg| {
+            encode_bytes(*arg).as_slice().to_c_str()
+        }).collect();
+        let mut pointers: ~[*c_char] = args.iter().map(|arg| arg.with_ref(|p| p)).collect();
+        pointers.push(ptr::null());
+        encode_bytes(program).as_slice().with_c_str(|path| unsafe {
+            libc::execv(path, pointers.as_ptr());
+        });
+        match os::errno() as c_int {
+            ENOENT => { NotFound(program.to_owned()) }
+            EACCES => { NotExecutable(program.to_owned()) }
+            _      => { SpawnFailed(program.to_owned(), os::last_os_error()) }
+        }
+    }
+
+    // Open the file at path with flags, creating it (subject to the umask)
+    // if asked to.
+    pub fn open_fd(path: &str, flags: c_int) -> Result<c_int, ShellError> {
+        let fd = encode_bytes(path).as_slice().with_c_str(|p| unsafe {
+            libc::open(p, flags, 0x1b6)
+        });
+        if fd < 0 {
+            return Err(RedirectFailed(path.to_owned(), os::last_os_error()));
+        }
+        Ok(fd)
+    }
+
+    // Make the shell's own fd refer to the file at path, opened with flags.
+    pub fn redirect_fd(fd: c_int, path: &str, flags: c_int) -> Result<(), ShellError> {
+        let file = match open_fd(path, flags) {
+            Ok(file) => { file }
+            Err(e)   => { return Err(e); }
+        };
+        if file != fd {
+            let result = unsafe { libc::dup2(file, fd) };
+            unsafe { libc::close(file); }
+            if result < 0 {
+                return Err(RedirectFailed(path.to_owned(), os::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    // Make the shell's own fd a copy of target.
+    pub fn duplicate_fd(fd: c_int, target: c_int) -> Result<(), ShellError> {
+        if unsafe { libc::dup2(target, fd) } < 0 {
+            return Err(RedirectFailed(target.to_str(), os::last_os_error()));
+        }
+        Ok(())
+    }
+
+    pub fn close_fd(fd: c_int) {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+
+    // Write all of text to fd, giving up if the reader has gone. A pipe is
+    // written from a task of its own with a copy of fd, since it can fill
+    // up before anything reads it; the caller may close fd straight away.
+    pub fn write_fd(fd: c_int, text: ~str, pipe: bool) {
+        if !pipe {
+            write_all(fd, text);
+            return;
+        }
+        let copy = unsafe { libc::dup(fd) };
+        if copy < 0 {
+            return;
+        }
+        spawn(proc() {
+            write_all(copy, text);
+            close_fd(copy);
+        });
+    }
+
+    fn write_all(fd: c_int, text: &str) {
+        let bytes = encode_bytes(text);
+        let mut written = 0;
+        while written < bytes.len() {
+            let rest = bytes.slice_from(written);
+            let count = unsafe {
+                libc::write(fd, rest.as_ptr() as *libc::c_void, rest.len() as libc::size_t)
+            };
+            if count < 0 {
+                if os::errno() as c_int == EINTR {
+                    continue;
+                }
+                return;
+            }
+            written += count as uint;
+        }
+    }
+
+    // LC_ALL from <locale.h>.
+    #[cfg(target_os = "linux")]
+    #[cfg(target_os = "android")]
+    static LC_ALL : c_int = 6;
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "freebsd")]
+    static LC_ALL : c_int = 0;
+
+    extern {
+        fn setlocale(category: c_int, locale: *c_char) -> *c_char;
+    }
+
+    // Take the locale from the environment. C programs start in the "C"
+    // locale, where the regex library sees UTF-8 text as separate bytes.
+    pub fn use_environment_locale() {
+        "".with_c_str(|p| unsafe { setlocale(LC_ALL, p); });
+    }
+
+    // How gash was asked to run.
+    pub enum Mode {
+        // Read commands from the terminal.
+        Interactive,
+        // Run the argument to -c.
+        Command(~str),
+        // Run the commands in a script file.
+        Script(~str),
+        // Join process group pgid if there is one, taking the terminal if
+        // in the foreground, then exec program with argv. Only gash itself
+        // starts this, as `gash --launch PGID|- fg|bg PROGRAM ARGV...`; see
+        // launcher().
+        Launch(Option<pid_t>, bool, ~str, ~[~str]),
+    }
+
+    // Wrap program and argv (whose first element is its name, which needn't
+    // be program) so that gash starts it in launch mode: the process joins
+    // the group pgid (0 for one of its own), if given, before the program
+    // runs. std::run always makes argv[0] the program it runs, so this is
+    // also how a command gets the name it was typed as when std::run can't
+    // be given that name to run.
+    pub fn launcher(program: &str, argv: &[~str], pgid: Option<pid_t>, foreground: bool)
+            -> (~str, ~[~str]) {
+        let group = match pgid {
+            Some(pgid) => { pgid.to_str() }
+            None       => { ~"-" }
+        };
+        let mut args = ~[~"--launch", group,
+                         if foreground { ~"fg" } else { ~"bg" }, program.to_owned()];
+        args.push_all(argv);
+        (self_exe(), args)
     }
 
     // Begin processing program arguments and initiate the parameters.
-    pub fn get_cmdline_from_args() -> Option<~str> {
-        let args = os::args();
+    pub fn get_mode_from_args() -> Mode {
+        // The runtime doesn't check that arguments are UTF-8, so those that
+        // aren't, like a subshell's commands, are read the way input is.
+        let args: ~[~str] = os::args().iter().map(|arg| decode_bytes(arg.as_bytes())).collect();
+        if args.len() > 5 && args[1] == ~"--launch" {
+            let pgid = match (args[2].as_slice(), from_str::<pid_t>(args[2])) {
+                ("-", _)         => { None }
+                (_, Some(pgid)) => { Some(pgid) }
+                _               => { fail!("bad process group for --launch") }
+            };
+            return Launch(pgid, args[3] == ~"fg", args[4].clone(), args.slice_from(5).to_owned());
+        }
         
         let opts = ~[
             getopts::optopt("c")
@@ -99,9 +529,87 @@ pub mod helpers {
                 Some(cmd_str) => {cmd_str.to_owned()}, 
                 None          => {~""}
             };
-            return Some(cmd_str);
+            return Command(cmd_str);
+        } else if matches.free.len() > 0 {
+            return Script(matches.free[0].to_owned());
         } else {
-            return None;
+            return Interactive;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{strip_comments, split_words, expand_words, decode_bytes, encode_bytes,
+                    has_raw_bytes};
+
+        fn expand(line: &str) -> ~str {
+            expand_words(line, |name| {
+                match name {
+                    "A"  => { Some(~"a b") }
+                    "OP" => { Some(~"x>y; z|w &") }
+                    "Q"  => { Some(~"it's \"$A\"") }
+                    _    => { None }
+                }
+            })
+        }
+
+        fn words(line: &str) -> ~[~str] {
+            split_words(expand(line))
+        }
+
+        #[test]
+        fn comments_start_only_at_a_word() {
+            assert_eq!(strip_comments("echo a # b"), ~"echo a ");
+            assert_eq!(strip_comments("# all of it"), ~"");
+            assert_eq!(strip_comments("echo a#b"), ~"echo a#b");
+            assert_eq!(strip_comments("echo $#"), ~"echo $#");
+            assert_eq!(strip_comments("a;# b\nc # d\ne"), ~"a;\nc \ne");
+        }
+
+        #[test]
+        fn quoted_and_escaped_hashes_are_not_comments() {
+            assert_eq!(strip_comments("echo '#' x"), ~"echo '#' x");
+            assert_eq!(strip_comments("echo \"a # b\" # c"), ~"echo \"a # b\" ");
+            assert_eq!(strip_comments("echo \\# x"), ~"echo \\# x");
+            assert_eq!(strip_comments("echo 'it''s' #"), ~"echo 'it''s' ");
+            assert_eq!(strip_comments("echo \"\\\" #\" x"), ~"echo \"\\\" #\" x");
+        }
+
+        #[test]
+        fn split_words_removes_quotes() {
+            assert_eq!(split_words("a  b\tc"), ~[~"a", ~"b", ~"c"]);
+            assert_eq!(split_words("'a b' \"c d\""), ~[~"a b", ~"c d"]);
+            assert_eq!(split_words("a\"b c\"d '' \"\""), ~[~"ab cd", ~"", ~""]);
+            assert_eq!(split_words("a\\ b \\'c"), ~[~"a b", ~"'c"]);
+            assert_eq!(split_words("\"\\\" \\n\" '\\n'"), ~[~"\" \\n", ~"\\n"]);
+        }
+
+        #[test]
+        fn single_quotes_and_backslashes_stop_expansion() {
+            assert_eq!(words("echo '$A' \\$A \"$A\""), ~[~"echo", ~"$A", ~"$A", ~"a b"]);
+            assert_eq!(words("echo \"'$A'\""), ~[~"echo", ~"'a b'"]);
+        }
+
+        #[test]
+        fn values_are_not_read_as_operators() {
+            assert_eq!(expand("echo $OP"), ~"echo 'x>y;' 'z|w' '&'");
+            assert_eq!(words("echo $OP"), ~[~"echo", ~"x>y;", ~"z|w", ~"&"]);
+            assert_eq!(words("echo \"$OP\""), ~[~"echo", ~"x>y; z|w &"]);
+            assert_eq!(words("echo $Q"), ~[~"echo", ~"it's", ~"\"$A\""]);
+            assert_eq!(words("echo \"$Q\""), ~[~"echo", ~"it's \"$A\""]);
+            assert_eq!(words("echo x$A$UNSET"), ~[~"echo", ~"xa", ~"b"]);
+        }
+
+        #[test]
+        fn bytes_that_are_not_utf8_survive() {
+            let bytes = ~[0x61u8, 0xff, 0xc3, 0xa9, 0xc3, 0x20, 0xe2, 0x82, 0xac, 0x80];
+            let text = decode_bytes(bytes);
+            assert_eq!(text.char_len(), 7);
+            assert!(text.starts_with("a") && text.contains("\u00e9") && text.contains("\u20ac"));
+            assert!(has_raw_bytes(text));
+            assert_eq!(encode_bytes(text), bytes);
+            assert!(!has_raw_bytes(decode_bytes("caf\u00e9".as_bytes())));
+            assert_eq!(split_words(text).len(), 2);
         }
     }
 }
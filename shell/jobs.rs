@@ -0,0 +1,574 @@
+#[allow(dead_code)]
+pub mod jobs {
+    // The job table. Every background or stopped job gets a number, starting
+    // from 1, which it keeps until it finishes and is reported. Builtins name
+    // jobs with job specs:
+    //
+    //     %n          job number n
+    //     %+, %%, %   the current job
+    //     %-          the previous job
+    //     %name       the job whose command line starts with name
+    //     %?text      the job whose command line contains text
+    //
+    // Each background process has a task blocked in Process::finish on it,
+    // which reaps it as soon as it exits and sends the exit down a channel
+    // shared by all the jobs, so finished jobs never linger as zombies and
+    // the table only has to drain that channel to catch up. Stops don't
+    // come down the channel: the process groups of running jobs are looked
+    // at for them whenever the table is reaped or waited on.
+    use std::vec::{VecIterator, VecMutIterator};
+    use std::comm::SharedChan;
+    use std::hashmap::HashMap;
+    use std::io::process::{ProcessExit, ExitStatus, ExitSignal};
+    use std::libc::types::os::arch::posix88::pid_t;
+    use std::io::timer;
+    use shellprocess::bg::{BgProcess, Capture};
+    use shellprocess::wait::is_stopped;
+    use signals::signals::signal_name;
+
+    // What a job is doing, as `jobs` reports it.
+    #[deriving(Eq, Clone)]
+    pub enum JobState {
+        Running,
+        Stopped,
+        Done,
+        Exited(int),
+        Killed(int),
+    }
+
+    impl JobState {
+        pub fn from_exit(exit: ProcessExit) -> JobState {
+            match exit {
+                ExitStatus(0)      => { Done }
+                ExitStatus(status) => { Exited(status) }
+                ExitSignal(signal) => { Killed(signal) }
+            }
+        }
+
+        // The exit status of a finished job, as $? would report it.
+        pub fn status(&self) -> Option<int> {
+            match *self {
+                Done           => { Some(0) }
+                Exited(status) => { Some(status) }
+                Killed(signal) => { Some(128 + signal) }
+                _              => { None }
+            }
+        }
+
+        pub fn is_finished(&self) -> bool {
+            match *self {
+                Running | Stopped => { false }
+                _                 => { true }
+            }
+        }
+    }
+
+    impl ToStr for JobState {
+        fn to_str(&self) -> ~str {
+            match *self {
+                Running        => { ~"Running" }
+                Stopped        => { ~"Stopped" }
+                Done           => { ~"Done" }
+                Exited(status) => { format!("Exit {:d}", status) }
+                Killed(signal) => {
+                    match signal_name(signal as i32) {
+                        Some(name) => { format!("Killed by SIG{:s}", name) }
+                        None       => { format!("Killed by signal {:d}", signal) }
+                    }
+                }
+            }
+        }
+    }
+
+    pub struct Job {
+        id       : uint,
+        cmd_line : ~str,
+        process  : ~BgProcess,
+        state    : JobState,
+        // When the job was last started, stopped or continued. The most
+        // recent one is the current job.
+        touched  : uint,
+        // When the job finished, on the same clock, so that jobs can be
+        // taken in the order they finished.
+        done_at  : uint,
+        // Whether the user has been told the job finished.
+        notified : bool,
+        // Marked by `disown -h`: not hung up when the shell exits.
+        nohup    : bool,
+    }
+
+    impl Job {
+        pub fn pid(&self) -> Option<pid_t> {
+            self.process.pid
+        }
+
+        // The process group signals for this job go to.
+        pub fn group(&self) -> Option<pid_t> {
+            self.process.group()
+        }
+    }
+
+    // A process's pid and how it exited.
+    pub type Exit = (pid_t, ProcessExit);
+
+    pub struct JobTable {
+        jobs      : ~[Job],
+        clock     : uint,
+        // Exits of background processes. Lent to a Watcher while the shell
+        // waits for input with `set -b` on.
+        exits     : Option<Port<Exit>>,
+        exit_chan : SharedChan<Exit>,
+        // Exit statuses of finished jobs that have left the table, by pid,
+        // so `wait` can still report them.
+        statuses  : HashMap<pid_t, int>,
+        // Captured output of jobs that have left the table, by job number,
+        // until the number is used again.
+        outputs   : HashMap<uint, Capture>,
+    }
+
+    // Announces jobs the moment they finish (set -b), from a task of its
+    // own, while the shell is blocked reading a line.
+    pub struct Watcher {
+        done : Port<(Port<Exit>, ~[Exit])>,
+    }
+
+    impl JobTable {
+        pub fn new() -> JobTable {
+            let (port, chan) = SharedChan::new();
+            JobTable {
+                jobs: ~[],
+                clock: 0,
+                exits: Some(port),
+                exit_chan: chan,
+                statuses: HashMap::new(),
+                outputs: HashMap::new(),
+            }
+        }
+
+        // Where a background process should send its exit.
+        pub fn exit_chan(&self) -> SharedChan<Exit> {
+            self.exit_chan.clone()
+        }
+
+        // Add a job, returning its number: one more than the highest number
+        // in use.
+        pub fn add(&mut self, cmd_line: ~str, process: ~BgProcess, state: JobState) -> uint {
+            let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+            self.outputs.remove(&id);
+            self.clock += 1;
+            self.jobs.push(Job {
+                id: id,
+                cmd_line: cmd_line,
+                process: process,
+                state: state,
+                touched: self.clock,
+                done_at: 0,
+                notified: false,
+                nohup: false,
+            });
+            id
+        }
+
+        // Mark a job as the most recently used one.
+        pub fn touch(&mut self, id: uint) {
+            self.clock += 1;
+            let clock = self.clock;
+            match self.get_mut(id) {
+                Some(job) => { job.touched = clock; }
+                None      => { }
+            }
+        }
+
+        pub fn get<'a>(&'a self, id: uint) -> Option<&'a Job> {
+            self.jobs.iter().find(|job| job.id == id)
+        }
+
+        pub fn get_mut<'a>(&'a mut self, id: uint) -> Option<&'a mut Job> {
+            self.jobs.mut_iter().find(|job| job.id == id)
+        }
+
+        // Drop a job from the table, remembering its exit status if it has
+        // finished.
+        pub fn remove(&mut self, id: uint) -> Option<Job> {
+            let mut job = match self.jobs.iter().position(|job| job.id == id) {
+                Some(index) => { self.jobs.remove(index) }
+                None        => { return None; }
+            };
+            match (job.pid(), job.state.status()) {
+                (Some(pid), Some(status)) => { self.statuses.insert(pid, status); }
+                _                         => { }
+            }
+            match job.process.output.take() {
+                Some(output) => { self.outputs.insert(id, output); }
+                None         => { }
+            }
+            Some(job)
+        }
+
+        // What a job has written, if its output is being captured. Jobs
+        // that have left the table can still be asked about.
+        pub fn output(&mut self, id: uint) -> Option<~[u8]> {
+            match self.get_mut(id) {
+                Some(job) => {
+                    return match job.process.output {
+                        Some(ref mut output) => { Some(output.contents()) }
+                        None                 => { None }
+                    };
+                }
+                None => {
+                }
+            }
+            self.outputs.find_mut(&id).map(|output| output.contents())
+        }
+
+        // The exit status of a finished process that is no longer in the
+        // table. Once taken, it is forgotten.
+        pub fn take_status(&mut self, pid: pid_t) -> Option<int> {
+            self.statuses.pop(&pid)
+        }
+
+        pub fn iter<'a>(&'a self) -> VecIterator<'a, Job> {
+            self.jobs.iter()
+        }
+
+        pub fn mut_iter<'a>(&'a mut self) -> VecMutIterator<'a, Job> {
+            self.jobs.mut_iter()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.jobs.len() == 0
+        }
+
+        // The current job (%+): the most recently stopped job if any are
+        // stopped, otherwise the most recently started one.
+        pub fn current(&self) -> Option<uint> {
+            self.ranked(0, false)
+        }
+
+        // The previous job (%-): the one that would be current if the
+        // current job went away.
+        pub fn previous(&self) -> Option<uint> {
+            self.ranked(1, false)
+        }
+
+        // The nth job by rank. Finished jobs only count when asked for, to
+        // mark them in reports the way they were marked while running.
+        fn ranked(&self, n: uint, finished: bool) -> Option<uint> {
+            let mut ranks: ~[(bool, uint, uint)] = self.jobs.iter()
+                .filter(|job| finished || !job.state.is_finished())
+                .map(|job| (job.state == Stopped, job.touched, job.id))
+                .collect();
+            ranks.sort();
+            ranks.reverse();
+            if n < ranks.len() {
+                let (_, _, id) = ranks[n];
+                Some(id)
+            }
+            else {
+                None
+            }
+        }
+
+        // Find the job a job spec refers to, or say why there isn't one.
+        pub fn find(&self, spec: &str) -> Result<uint, ~str> {
+            let found = if spec == "%" || spec == "%%" || spec == "%+" {
+                self.current()
+            }
+            else if spec == "%-" {
+                self.previous()
+            }
+            else if spec.starts_with("%?") {
+                return self.find_by(spec, |job| job.cmd_line.contains(spec.slice_from(2)));
+            }
+            else if spec.starts_with("%") {
+                match from_str::<uint>(spec.slice_from(1)) {
+                    Some(id) => { self.get(id).map(|job| job.id) }
+                    None => {
+                        return self.find_by(spec, |job| {
+                            job.cmd_line.starts_with(spec.slice_from(1))
+                        });
+                    }
+                }
+            }
+            else {
+                None
+            };
+            match found {
+                Some(id) => { Ok(id) }
+                None     => { Err(format!("{:s}: no such job", spec)) }
+            }
+        }
+
+        // The only job matching pred.
+        fn find_by(&self, spec: &str, pred: |&Job| -> bool) -> Result<uint, ~str> {
+            let mut found = None;
+            for job in self.jobs.iter() {
+                if pred(job) {
+                    if found.is_some() {
+                        return Err(format!("{:s}: ambiguous job spec", spec));
+                    }
+                    found = Some(job.id);
+                }
+            }
+            match found {
+                Some(id) => { Ok(id) }
+                None     => { Err(format!("{:s}: no such job", spec)) }
+            }
+        }
+
+        // Record the exits that have arrived since the last look, marking
+        // the jobs that have finished. Returns their numbers.
+        pub fn reap(&mut self) -> ~[uint] {
+            // Keep captured output flowing while we're here.
+            for job in self.jobs.mut_iter() {
+                match job.process.output {
+                    Some(ref mut output) => { output.drain(); }
+                    None                 => { }
+                }
+            }
+            self.check_stops();
+            let mut finished = ~[];
+            loop {
+                let exit = match self.exits {
+                    Some(ref port) => { port.try_recv() }
+                    None           => { None }
+                };
+                match exit {
+                    Some((pid, exit)) => {
+                        match self.finish(pid, exit) {
+                            Some(id) => { finished.push(id); }
+                            None     => { }
+                        }
+                    }
+                    None => {
+                        return finished;
+                    }
+                }
+            }
+        }
+
+        // Block until the next background process exits, and record it, or
+        // until a running job is stopped. Nothing announces a stop, so
+        // while no exit arrives the jobs are looked at every few
+        // milliseconds.
+        pub fn wait_next(&mut self) {
+            loop {
+                match self.exits.get_ref().try_recv() {
+                    Some((pid, exit)) => {
+                        self.finish(pid, exit);
+                        return;
+                    }
+                    None => {
+                    }
+                }
+                if self.check_stops().len() > 0 {
+                    return;
+                }
+                timer::sleep(10);
+            }
+        }
+
+        // Mark the running jobs that have been stopped since the last look,
+        // e.g. by `kill -STOP`, returning their numbers.
+        fn check_stops(&mut self) -> ~[uint] {
+            let stopped: ~[uint] = self.jobs.iter()
+                .filter(|job| job.state == Running && job.group().map_default(false, |pgid| is_stopped(pgid)))
+                .map(|job| job.id)
+                .collect();
+            for &id in stopped.iter() {
+                self.get_mut(id).unwrap().state = Stopped;
+                self.touch(id);
+            }
+            stopped
+        }
+
+        // Of the jobs ids, the one that finished first, if any has.
+        pub fn first_finished(&self, ids: &[uint]) -> Option<uint> {
+            ids.iter().filter_map(|&id| self.get(id))
+                .filter(|job| job.state.is_finished())
+                .min_by(|job| job.done_at)
+                .map(|job| job.id)
+        }
+
+        // Block until the job has finished.
+        pub fn wait_for(&mut self, id: uint) {
+            loop {
+                match self.get(id) {
+                    Some(job) if !job.state.is_finished() => { }
+                    _ => { return; }
+                }
+                self.wait_next();
+            }
+        }
+
+        // Record the exit of pid, returning the number of its job.
+        fn finish(&mut self, pid: pid_t, exit: ProcessExit) -> Option<uint> {
+            self.clock += 1;
+            let clock = self.clock;
+            match self.jobs.mut_iter().find(|job| job.pid() == Some(pid)) {
+                Some(job) => {
+                    job.state = JobState::from_exit(exit);
+                    job.done_at = clock;
+                    return Some(job.id);
+                }
+                None => {
+                }
+            }
+            self.statuses.insert(pid, JobState::from_exit(exit).status().unwrap());
+            None
+        }
+
+        // Hand the exits over to a task that prints a notice for each job as
+        // it finishes. The notices are worked out up front, since the table
+        // can't change while the shell is reading.
+        pub fn watch(&mut self) -> Option<Watcher> {
+            let exits = match self.exits.take() {
+                Some(exits) => { exits }
+                None        => { return None; }
+            };
+            let notices: ~[(pid_t, uint, char, ~str)] = self.jobs.iter()
+                .filter(|job| !job.state.is_finished() && job.pid().is_some())
+                .map(|job| (job.pid().unwrap(), job.id, self.mark(job.id), job.cmd_line.clone()))
+                .collect();
+            let (done, done_chan) = Chan::new();
+            spawn(proc() {
+                let mut seen = ~[];
+                loop {
+                    let (pid, exit) = exits.recv();
+                    // Pid 0 is unwatch asking for the port back.
+                    if pid == 0 {
+                        break;
+                    }
+                    for &(job_pid, id, mark, ref cmd_line) in notices.iter() {
+                        if job_pid == pid {
+                            println(notice(id, mark, None, JobState::from_exit(exit),
+                                           cmd_line.as_slice()));
+                        }
+                    }
+                    seen.push((pid, exit));
+                }
+                done_chan.send((exits, seen));
+            });
+            Some(Watcher { done: done })
+        }
+
+        // Take the exits back from a Watcher, recording what it saw. The jobs
+        // it announced aren't announced again.
+        pub fn unwatch(&mut self, watcher: Watcher) {
+            self.exit_chan.send((0, ExitStatus(0)));
+            let (exits, seen) = watcher.done.recv();
+            self.exits = Some(exits);
+            for (pid, exit) in seen.move_iter() {
+                match self.finish(pid, exit) {
+                    Some(id) => { self.get_mut(id).unwrap().notified = true; }
+                    None     => { }
+                }
+            }
+        }
+
+        // '+' for the current job, '-' for the previous one.
+        fn mark(&self, id: uint) -> char {
+            if Some(id) == self.ranked(0, true) {
+                '+'
+            }
+            else if Some(id) == self.ranked(1, true) {
+                '-'
+            }
+            else {
+                ' '
+            }
+        }
+
+        // A job's line in the output of `jobs`, e.g.
+        //     [1]+  Running                 sleep 10 &
+        // With long set, the pid goes after the job number.
+        pub fn describe(&self, id: uint, long: bool) -> ~str {
+            let job = match self.get(id) {
+                Some(job) => { job }
+                None      => { return ~""; }
+            };
+            let pid = if long { job.pid() } else { None };
+            notice(id, self.mark(id), pid, job.state, job.cmd_line)
+        }
+    }
+
+    // A job's line in a report, with its pid if one is given.
+    fn notice(id: uint, mark: char, pid: Option<pid_t>, state: JobState, cmd_line: &str) -> ~str {
+        let state = state.to_str();
+        let padding = if state.len() < 24 { " ".repeat(24 - state.len()) } else { ~" " };
+        let pid = match pid {
+            Some(pid) => { format!(" {:d}", pid) }
+            None      => { ~" " }
+        };
+        format!("[{:u}]{}{:s} {:s}{:s}{:s}", id, mark, pid, state, padding, cmd_line)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{JobTable, Running, Stopped};
+        use shellprocess::bg::BgProcess;
+
+        // A table of jobs that were started in order and are all running.
+        fn table(cmd_lines: &[&str]) -> JobTable {
+            let mut jobs = JobTable::new();
+            for cmd_line in cmd_lines.iter() {
+                jobs.add(cmd_line.to_owned(), ~BgProcess::new(~"sleep", ~[]), Running);
+            }
+            jobs
+        }
+
+        #[test]
+        fn numbers_find_their_jobs() {
+            let jobs = table(&["sleep 10", "sleep 20"]);
+            assert_eq!(jobs.find("%1"), Ok(1));
+            assert_eq!(jobs.find("%2"), Ok(2));
+            assert_eq!(jobs.find("%3"), Err(~"%3: no such job"));
+            assert_eq!(jobs.find("1"), Err(~"1: no such job"));
+        }
+
+        #[test]
+        fn current_and_previous_are_the_latest_jobs() {
+            let mut jobs = table(&["sleep 10", "sleep 20", "sleep 30"]);
+            assert_eq!(jobs.find("%"), Ok(3));
+            assert_eq!(jobs.find("%%"), Ok(3));
+            assert_eq!(jobs.find("%+"), Ok(3));
+            assert_eq!(jobs.find("%-"), Ok(2));
+            jobs.touch(1);
+            assert_eq!(jobs.find("%+"), Ok(1));
+            assert_eq!(jobs.find("%-"), Ok(3));
+        }
+
+        #[test]
+        fn stopped_jobs_come_before_running_ones() {
+            let mut jobs = table(&["sleep 10", "sleep 20", "sleep 30"]);
+            jobs.get_mut(1).unwrap().state = Stopped;
+            assert_eq!(jobs.find("%+"), Ok(1));
+            assert_eq!(jobs.find("%-"), Ok(3));
+        }
+
+        #[test]
+        fn no_current_job_in_an_empty_table() {
+            let jobs = table(&[]);
+            assert_eq!(jobs.find("%%"), Err(~"%%: no such job"));
+            assert_eq!(jobs.find("%-"), Err(~"%-: no such job"));
+        }
+
+        #[test]
+        fn names_match_the_start_of_the_command_line() {
+            let jobs = table(&["sleep 10", "vim notes", "sleepy"]);
+            assert_eq!(jobs.find("%vim"), Ok(2));
+            assert_eq!(jobs.find("%sleepy"), Ok(3));
+            assert_eq!(jobs.find("%notes"), Err(~"%notes: no such job"));
+            assert_eq!(jobs.find("%sleep"), Err(~"%sleep: ambiguous job spec"));
+        }
+
+        #[test]
+        fn question_marks_match_anywhere_in_the_command_line() {
+            let jobs = table(&["sleep 10", "vim notes", "less notes"]);
+            assert_eq!(jobs.find("%?10"), Ok(1));
+            assert_eq!(jobs.find("%?vim"), Ok(2));
+            assert_eq!(jobs.find("%?notes"), Err(~"%?notes: ambiguous job spec"));
+            assert_eq!(jobs.find("%?emacs"), Err(~"%?emacs: no such job"));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+#[allow(dead_code)]
+pub mod limits {
+    // Resource limits and the file creation mask. Both belong to the shell
+    // process and are inherited by every child it spawns, so setting them
+    // here is how `ulimit` and `umask` reach the commands run afterwards.
+    use std::libc::{c_int, c_uint};
+    use std::libc::consts::os::posix88::{S_IRWXU, S_IRUSR, S_IWUSR, S_IXUSR};
+    use std::io::fs::File;
+    use std::io::io_error;
+    use std::num::{from_str_radix, CheckedMul};
+    use std::os;
+    use std::str;
+
+    // The permission bits of each class, which std::libc only has for the
+    // user: the group's and others' are the same bits shifted down.
+    static USER_BITS  : u32 = S_IRWXU as u32;
+    static GROUP_BITS : u32 = USER_BITS >> 3;
+    static OTHER_BITS : u32 = USER_BITS >> 6;
+    pub static ALL_PERMISSIONS : u32 = USER_BITS | GROUP_BITS | OTHER_BITS;
+    static READ_BITS  : u32 = (S_IRUSR as u32) | (S_IRUSR as u32) >> 3 | (S_IRUSR as u32) >> 6;
+    static WRITE_BITS : u32 = (S_IWUSR as u32) | (S_IWUSR as u32) >> 3 | (S_IWUSR as u32) >> 6;
+    static EXEC_BITS  : u32 = (S_IXUSR as u32) | (S_IXUSR as u32) >> 3 | (S_IXUSR as u32) >> 6;
+
+    // struct rlimit from <sys/resource.h> on Linux.
+    struct RLimit {
+        rlim_cur : u64,
+        rlim_max : u64,
+    }
+
+    pub static RLIM_INFINITY : u64 = !0;
+
+    extern {
+        fn getrlimit(resource: c_int, rlim: *mut RLimit) -> c_int;
+        fn setrlimit(resource: c_int, rlim: *RLimit) -> c_int;
+        fn umask(mask: c_uint) -> c_uint;
+    }
+
+    // A limit `ulimit` knows: its option letter, what it limits, the unit
+    // its values are given in, how many bytes (or whatever) that unit is,
+    // and the resource number.
+    pub struct Resource {
+        option : char,
+        name   : &'static str,
+        unit   : &'static str,
+        scale  : u64,
+        id     : c_int,
+    }
+
+    // Every resource setrlimit can limit, in the order `ulimit -a` lists
+    // them.
+    pub static RESOURCES: &'static [Resource] = &[
+        Resource { option: 'R', name: "real-time non-blocking time", unit: "microseconds",
+                   scale: 1, id: 15 },
+        Resource { option: 'c', name: "core file size", unit: "blocks", scale: 512, id: 4 },
+        Resource { option: 'd', name: "data seg size", unit: "kbytes", scale: 1024, id: 2 },
+        Resource { option: 'e', name: "scheduling priority", unit: "", scale: 1, id: 13 },
+        Resource { option: 'f', name: "file size", unit: "blocks", scale: 512, id: 1 },
+        Resource { option: 'i', name: "pending signals", unit: "", scale: 1, id: 11 },
+        Resource { option: 'l', name: "max locked memory", unit: "kbytes", scale: 1024, id: 8 },
+        Resource { option: 'm', name: "max memory size", unit: "kbytes", scale: 1024, id: 5 },
+        Resource { option: 'n', name: "open files", unit: "", scale: 1, id: 7 },
+        Resource { option: 'q', name: "POSIX message queues", unit: "bytes", scale: 1, id: 12 },
+        Resource { option: 'r', name: "real-time priority", unit: "", scale: 1, id: 14 },
+        Resource { option: 's', name: "stack size", unit: "kbytes", scale: 1024, id: 3 },
+        Resource { option: 't', name: "cpu time", unit: "seconds", scale: 1, id: 0 },
+        Resource { option: 'u', name: "max user processes", unit: "", scale: 1, id: 6 },
+        Resource { option: 'v', name: "virtual memory", unit: "kbytes", scale: 1024, id: 9 },
+        Resource { option: 'x', name: "file locks", unit: "", scale: 1, id: 10 },
+    ];
+
+    pub fn find_resource(option: char) -> Option<&'static Resource> {
+        RESOURCES.iter().find(|resource| resource.option == option)
+    }
+
+    impl Resource {
+        // The (soft, hard) limits, in the resource's unit.
+        pub fn get(&self) -> Result<(u64, u64), ~str> {
+            let mut limit = RLimit { rlim_cur: 0, rlim_max: 0 };
+            if unsafe { getrlimit(self.id, &mut limit) } != 0 {
+                return Err(os::last_os_error());
+            }
+            Ok((self.from_raw(limit.rlim_cur), self.from_raw(limit.rlim_max)))
+        }
+
+        // Set the soft limit, the hard limit or both, in the resource's
+        // unit. A hard limit can only be raised again by root.
+        pub fn set(&self, soft: Option<u64>, hard: Option<u64>) -> Result<(), ~str> {
+            let mut limit = RLimit { rlim_cur: 0, rlim_max: 0 };
+            if unsafe { getrlimit(self.id, &mut limit) } != 0 {
+                return Err(os::last_os_error());
+            }
+            match soft.map(|soft| self.to_raw(soft)) {
+                Some(Ok(soft)) => { limit.rlim_cur = soft; }
+                Some(Err(e))   => { return Err(e); }
+                None           => { }
+            }
+            match hard.map(|hard| self.to_raw(hard)) {
+                Some(Ok(hard)) => { limit.rlim_max = hard; }
+                Some(Err(e))   => { return Err(e); }
+                None           => { }
+            }
+            if unsafe { setrlimit(self.id, &limit) } != 0 {
+                return Err(os::last_os_error());
+            }
+            Ok(())
+        }
+
+        // The heading `ulimit -a` gives the resource, e.g.
+        // "core file size (blocks, -c)".
+        pub fn describe(&self) -> ~str {
+            if self.unit == "" {
+                format!("{:s} (-{})", self.name, self.option)
+            }
+            else {
+                format!("{:s} ({:s}, -{})", self.name, self.unit, self.option)
+            }
+        }
+
+        fn from_raw(&self, raw: u64) -> u64 {
+            if raw == RLIM_INFINITY { raw } else { raw / self.scale }
+        }
+
+        // A value in the resource's unit as setrlimit takes it, unless it
+        // is too large to be one.
+        fn to_raw(&self, value: u64) -> Result<u64, ~str> {
+            if value == RLIM_INFINITY {
+                return Ok(value);
+            }
+            match value.checked_mul(&self.scale) {
+                Some(raw) if raw != RLIM_INFINITY => { Ok(raw) }
+                _                                 => { Err(~"value too large") }
+            }
+        }
+    }
+
+    // The file creation mask. Linux shows it in /proc/self/status; older
+    // kernels don't, and there the only way to read it is to set it and
+    // put it back, which for a moment leaves the process with the wrong
+    // mask. The shell's other tasks don't create files, so nothing of ours
+    // can fall in that window.
+    pub fn get_umask() -> u32 {
+        match proc_umask() {
+            Some(mask) => { mask }
+            None => unsafe {
+                let mask = umask(0);
+                umask(mask);
+                mask as u32
+            }
+        }
+    }
+
+    // The "Umask:" line of /proc/self/status, which is in octal.
+    fn proc_umask() -> Option<u32> {
+        let status = io_error::cond.trap(|_| { }).inside(|| {
+            File::open(&Path::new("/proc/self/status")).map(|mut file| file.read_to_end())
+        });
+        let status = match status.and_then(|bytes| str::from_utf8_owned_opt(bytes)) {
+            Some(status) => { status }
+            None         => { return None; }
+        };
+        status.lines().find(|line| line.starts_with("Umask:"))
+            .and_then(|line| from_str_radix::<u32>(line.slice_from("Umask:".len()).trim(), 8))
+    }
+
+    pub fn set_umask(mask: u32) {
+        unsafe {
+            umask((mask & ALL_PERMISSIONS) as c_uint);
+        }
+    }
+
+    // The mask in the symbolic form `umask -S` prints: the permissions it
+    // allows, e.g. "u=rwx,g=rx,o=rx" for 022.
+    pub fn symbolic(mask: u32) -> ~str {
+        let allowed = !mask & ALL_PERMISSIONS;
+        let parts: ~[~str] = [('u', 6), ('g', 3), ('o', 0)].iter().map(|&(who, shift)| {
+            let bits = (allowed >> shift) & 7;
+            let mut part = format!("{}=", who);
+            if bits & 4 != 0 { part.push_char('r'); }
+            if bits & 2 != 0 { part.push_char('w'); }
+            if bits & 1 != 0 { part.push_char('x'); }
+            part
+        }).collect();
+        parts.connect(",")
+    }
+
+    // Apply a symbolic mode like "u=rwx,g-w,o=" to mask, returning the new
+    // mask. Like chmod's, the mode says which permissions are allowed.
+    pub fn parse_symbolic(spec: &str, mask: u32) -> Option<u32> {
+        let mut allowed = !mask & ALL_PERMISSIONS;
+        for clause in spec.split(',') {
+            let op_at = match clause.find(|c: char| c == '=' || c == '+' || c == '-') {
+                Some(i) => { i }
+                None    => { return None; }
+            };
+            let mut who = 0;
+            for c in clause.slice_to(op_at).chars() {
+                who |= match c {
+                    'u' => { USER_BITS }
+                    'g' => { GROUP_BITS }
+                    'o' => { OTHER_BITS }
+                    'a' => { ALL_PERMISSIONS }
+                    _   => { return None; }
+                };
+            }
+            if who == 0 {
+                who = ALL_PERMISSIONS;
+            }
+            let mut perms = 0;
+            for c in clause.slice_from(op_at + 1).chars() {
+                perms |= match c {
+                    'r' => { READ_BITS }
+                    'w' => { WRITE_BITS }
+                    'x' => { EXEC_BITS }
+                    _   => { return None; }
+                };
+            }
+            let bits = perms & who;
+            match clause.char_at(op_at) {
+                '=' => { allowed = (allowed & !who) | bits; }
+                '+' => { allowed |= bits; }
+                _   => { allowed &= !bits; }
+            }
+        }
+        Some(!allowed & ALL_PERMISSIONS)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{find_resource, symbolic, parse_symbolic, RLIM_INFINITY};
+
+        #[test]
+        fn symbolic_lists_what_the_mask_allows() {
+            assert_eq!(symbolic(0x12), ~"u=rwx,g=rx,o=rx");
+            assert_eq!(symbolic(0x3f), ~"u=rwx,g=,o=");
+            assert_eq!(symbolic(0x1ff), ~"u=,g=,o=");
+        }
+
+        #[test]
+        fn symbolic_modes_set_add_and_remove_permissions() {
+            assert_eq!(parse_symbolic("u=rwx,g=rx,o=rx", 0x3f), Some(0x12));
+            assert_eq!(parse_symbolic("g-w", 0), Some(0x10));
+            assert_eq!(parse_symbolic("o=", 0x12), Some(0x17));
+            assert_eq!(parse_symbolic("go+w", 0x12), Some(0));
+        }
+
+        #[test]
+        fn symbolic_modes_without_who_mean_everyone() {
+            assert_eq!(parse_symbolic("+w", 0x12), Some(0));
+            assert_eq!(parse_symbolic("=r", 0), Some(0xdb));
+            assert_eq!(parse_symbolic("a=r", 0), Some(0xdb));
+        }
+
+        #[test]
+        fn symbolic_form_reads_back_as_the_same_mask() {
+            for &mask in [0, 0x2, 0x12, 0x17, 0x3f, 0x1ff].iter() {
+                assert_eq!(parse_symbolic(symbolic(mask).as_slice(), 0x1ff), Some(mask));
+            }
+        }
+
+        #[test]
+        fn bad_symbolic_modes_are_rejected() {
+            assert_eq!(parse_symbolic("u", 0), None);
+            assert_eq!(parse_symbolic("z=r", 0), None);
+            assert_eq!(parse_symbolic("u=q", 0), None);
+            assert_eq!(parse_symbolic("u=r,", 0), None);
+        }
+
+        #[test]
+        fn values_are_scaled_to_the_resource_unit() {
+            let core = find_resource('c').unwrap();
+            assert_eq!(core.to_raw(2), Ok(1024));
+            assert_eq!(core.from_raw(1024), 2);
+            let files = find_resource('n').unwrap();
+            assert_eq!(files.to_raw(1024), Ok(1024));
+        }
+
+        #[test]
+        fn unlimited_stays_unlimited() {
+            let core = find_resource('c').unwrap();
+            assert_eq!(core.to_raw(RLIM_INFINITY), Ok(RLIM_INFINITY));
+            assert_eq!(core.from_raw(RLIM_INFINITY), RLIM_INFINITY);
+        }
+
+        #[test]
+        fn values_that_overflow_are_too_large() {
+            let core = find_resource('c').unwrap();
+            assert_eq!(core.to_raw(RLIM_INFINITY / 512), Ok(RLIM_INFINITY / 512 * 512));
+            assert_eq!(core.to_raw(RLIM_INFINITY / 512 + 1), Err(~"value too large"));
+        }
+    }
+}
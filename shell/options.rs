@@ -0,0 +1,211 @@
+#[allow(dead_code)]
+pub mod options {
+    // Shell options: the ones `set` turns on with -x or -o xtrace and off
+    // with +x or +o xtrace, and the extended ones `shopt` sets and unsets.
+    // The letters of the `set` options that are on make up $-.
+
+    // A `set` option: its letter, if it has one, and its long name.
+    pub struct SetOption {
+        letter : Option<char>,
+        name   : &'static str,
+    }
+
+    // The `set` options, in the order `set -o` lists them.
+    pub static SET_OPTIONS: &'static [SetOption] = &[
+        SetOption { letter: None, name: "bgcapture" },
+        SetOption { letter: Some('e'), name: "errexit" },
+        SetOption { letter: Some('n'), name: "noexec" },
+        SetOption { letter: Some('C'), name: "noclobber" },
+        SetOption { letter: Some('f'), name: "noglob" },
+        SetOption { letter: Some('u'), name: "nounset" },
+        SetOption { letter: Some('b'), name: "notify" },
+        SetOption { letter: None, name: "pipefail" },
+        SetOption { letter: Some('v'), name: "verbose" },
+        SetOption { letter: Some('x'), name: "xtrace" },
+    ];
+
+    // The options only `shopt` knows, in the order it lists them.
+    pub static SHOPT_OPTIONS: &'static [&'static str] = &[
+        "checkjobs", "huponexit", "xpg_echo",
+    ];
+
+    // The long name of the `set` option with the given letter.
+    pub fn option_name(letter: char) -> Option<&'static str> {
+        SET_OPTIONS.iter().find(|option| option.letter == Some(letter)).map(|option| option.name)
+    }
+
+    #[deriving(Clone)]
+    pub struct Options {
+        // set -o bgcapture: keep background jobs' output for `jobs output`
+        // rather than letting it go to the terminal.
+        bgcapture : bool,
+        // set -e: exit as soon as a command fails.
+        errexit   : bool,
+        // set -n: read commands but don't run them (ignored interactively).
+        noexec    : bool,
+        // set -C: `>` won't overwrite an existing file.
+        noclobber : bool,
+        // set -f: no pathname expansion.
+        noglob    : bool,
+        // set -u: expanding an unset variable is an error.
+        nounset   : bool,
+        // set -b: report finished jobs at once, not before the next prompt.
+        notify    : bool,
+        // set -o pipefail: a pipeline fails if any of its commands does.
+        pipefail  : bool,
+        // set -v: echo input lines to stderr as they are read.
+        verbose   : bool,
+        // set -x: show each command on stderr, after $PS4, before it runs.
+        xtrace    : bool,
+        // shopt checkjobs: warn about jobs before exiting.
+        checkjobs : bool,
+        // shopt huponexit: hang up jobs when an interactive shell exits.
+        huponexit : bool,
+        // shopt xpg_echo: echo expands backslash escapes without -e.
+        xpg_echo  : bool,
+    }
+
+    impl Options {
+        pub fn new() -> Options {
+            Options {
+                bgcapture: false,
+                errexit: false,
+                noexec: false,
+                noclobber: false,
+                noglob: false,
+                nounset: false,
+                notify: false,
+                pipefail: false,
+                verbose: false,
+                xtrace: false,
+                checkjobs: true,
+                huponexit: true,
+                xpg_echo: false,
+            }
+        }
+
+        fn flag<'a>(&'a mut self, name: &str) -> Option<&'a mut bool> {
+            match name {
+                "bgcapture" => { Some(&mut self.bgcapture) }
+                "errexit"   => { Some(&mut self.errexit) }
+                "noexec"    => { Some(&mut self.noexec) }
+                "noclobber" => { Some(&mut self.noclobber) }
+                "noglob"    => { Some(&mut self.noglob) }
+                "nounset"   => { Some(&mut self.nounset) }
+                "notify"    => { Some(&mut self.notify) }
+                "pipefail"  => { Some(&mut self.pipefail) }
+                "verbose"   => { Some(&mut self.verbose) }
+                "xtrace"    => { Some(&mut self.xtrace) }
+                "checkjobs" => { Some(&mut self.checkjobs) }
+                "huponexit" => { Some(&mut self.huponexit) }
+                "xpg_echo"  => { Some(&mut self.xpg_echo) }
+                _           => { None }
+            }
+        }
+
+        // Whether the named option is on, or None if there's no such
+        // option.
+        pub fn get(&self, name: &str) -> Option<bool> {
+            match name {
+                "bgcapture" => { Some(self.bgcapture) }
+                "errexit"   => { Some(self.errexit) }
+                "noexec"    => { Some(self.noexec) }
+                "noclobber" => { Some(self.noclobber) }
+                "noglob"    => { Some(self.noglob) }
+                "nounset"   => { Some(self.nounset) }
+                "notify"    => { Some(self.notify) }
+                "pipefail"  => { Some(self.pipefail) }
+                "verbose"   => { Some(self.verbose) }
+                "xtrace"    => { Some(self.xtrace) }
+                "checkjobs" => { Some(self.checkjobs) }
+                "huponexit" => { Some(self.huponexit) }
+                "xpg_echo"  => { Some(self.xpg_echo) }
+                _           => { None }
+            }
+        }
+
+        // Turn the named option on or off. Returns false if there's no such
+        // option.
+        pub fn set(&mut self, name: &str, on: bool) -> bool {
+            match self.flag(name) {
+                Some(flag) => {
+                    *flag = on;
+                    true
+                }
+                None => {
+                    false
+                }
+            }
+        }
+
+        // The letters of the `set` options that are on, for $-.
+        pub fn letters(&self) -> ~str {
+            let mut letters = ~"";
+            for option in SET_OPTIONS.iter() {
+                match option.letter {
+                    Some(letter) if self.get(option.name) == Some(true) => {
+                        letters.push_char(letter);
+                    }
+                    _ => { }
+                }
+            }
+            letters
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{Options, SET_OPTIONS, SHOPT_OPTIONS, option_name};
+
+        #[test]
+        fn letters_name_their_options() {
+            assert_eq!(option_name('e'), Some("errexit"));
+            assert_eq!(option_name('C'), Some("noclobber"));
+            assert_eq!(option_name('x'), Some("xtrace"));
+            assert_eq!(option_name('c'), None);
+            assert_eq!(option_name('z'), None);
+        }
+
+        #[test]
+        fn every_listed_option_can_be_set() {
+            let mut options = Options::new();
+            let names = SET_OPTIONS.iter().map(|option| option.name)
+                .chain(SHOPT_OPTIONS.iter().map(|&name| name));
+            for name in names {
+                assert!(options.set(name, true));
+                assert_eq!(options.get(name), Some(true));
+                assert!(options.set(name, false));
+                assert_eq!(options.get(name), Some(false));
+            }
+        }
+
+        #[test]
+        fn unknown_options_are_refused() {
+            let mut options = Options::new();
+            assert!(!options.set("xtracing", true));
+            assert_eq!(options.get("xtracing"), None);
+            assert_eq!(options.get(""), None);
+        }
+
+        #[test]
+        fn only_shopt_options_start_on() {
+            let options = Options::new();
+            assert_eq!(options.letters(), ~"");
+            assert_eq!(options.get("checkjobs"), Some(true));
+            assert_eq!(options.get("huponexit"), Some(true));
+            assert_eq!(options.get("xpg_echo"), Some(false));
+        }
+
+        #[test]
+        fn letters_follow_the_listing_order() {
+            let mut options = Options::new();
+            options.set("xtrace", true);
+            options.set("errexit", true);
+            options.set("pipefail", true);
+            options.set("noclobber", true);
+            assert_eq!(options.letters(), ~"eCx");
+            options.set("errexit", false);
+            assert_eq!(options.letters(), ~"Cx");
+        }
+    }
+}
@@ -29,14 +29,378 @@ pub mod pathtype {
     pub enum FilePermission {
         Read,
         Write,
+        // Write, even over an existing file under set -C (>|).
+        Clobber,
+    }
+}
+
+pub mod error {
+    use helpers::helpers::print_err;
+
+    // A syntax error in a command line: the offending token, where it
+    // starts, and a description of what should have been there instead.
+    pub struct ParseError {
+        line: uint,
+        column: uint,
+        token: ~str,
+        expected: ~str,
+    }
+
+    impl ParseError {
+        pub fn new(line: uint, column: uint, token: ~str, expected: ~str)
+                -> ParseError {
+            ParseError {
+                line: line,
+                column: column,
+                token: token,
+                expected: expected,
+            }
+        }
+
+        // Print the error, with the line of cmd_line it occurred on and a
+        // caret under the offending token.
+        pub fn report(&self, cmd_line: &str) {
+            // The column counts from the start of cmd_line, which may span
+            // several lines.
+            let before = cmd_line.slice_to(self.column);
+            let start = match before.rfind('\n') {
+                Some(i) => { i + 1 }
+                None    => { 0 }
+            };
+            let end = match cmd_line.slice_from(start).find('\n') {
+                Some(i) => { start + i }
+                None    => { cmd_line.len() }
+            };
+            let line = self.line + before.chars().count(|c| c == '\n');
+            let location = if line > 1 {
+                format!("line {:u}: ", line)
+            }
+            else {
+                ~""
+            };
+            print_err(format!("gash: {:s}syntax error near unexpected token '{:s}'",
+                              location, self.token));
+            print_err(cmd_line.slice(start, end));
+            let width = cmd_line.slice(start, self.column).char_len();
+            print_err(" ".repeat(width) + "^ " + self.expected.as_slice());
+        }
+    }
+}
+
+pub mod complete {
+    use std::util::replace;
+
+    // Decide whether input (possibly several lines) is a complete command, or
+    // whether the interactive reader should keep reading. Input is
+    // incomplete if it ends in a backslash, an open quote, an unfinished
+    // if/while/until/for/case, an unclosed '{' or '(', a trailing '|', '&&'
+    // or '||', or a here-doc whose delimiter hasn't been seen yet.
+    pub fn is_complete(input: &str) -> bool {
+        let mut scanner = Scanner::new();
+        for line in input.lines() {
+            scanner.scan_line(line);
+        }
+        scanner.is_complete()
+    }
+
+    // Join the lines of a complete command that end in a backslash to the
+    // line after, dropping the backslash and the newline. A backslash
+    // inside single quotes, or one that is itself escaped, isn't a line
+    // continuation and is kept.
+    pub fn join_lines(input: &str) -> ~str {
+        let mut scanner = Scanner::new();
+        let mut joined = ~"";
+        for line in input.lines() {
+            scanner.scan_line(line);
+            if scanner.continued {
+                joined.push_str(line.slice_to(line.len() - 1));
+            }
+            else {
+                joined.push_str(line);
+                joined.push_char('\n');
+            }
+        }
+        joined
+    }
+
+    struct Scanner {
+        // Closing keywords/brackets we are still waiting for.
+        closers     : ~[~str],
+        // Delimiters of here-docs whose bodies are being read.
+        heredocs    : ~[~str],
+        // Delimiters of here-docs started on the current line.
+        new_heredocs: ~[~str],
+        quote       : Option<char>,
+        word        : ~str,
+        // True if the next word is in command position.
+        cmd_start   : bool,
+        // True if the last thing seen was '|', '&&' or '||'.
+        dangling    : bool,
+        // True if the last line ended in a backslash that continues it,
+        // outside of quotes or inside double quotes.
+        continued   : bool,
+    }
+
+    impl Scanner {
+        fn new() -> Scanner {
+            Scanner {
+                closers: ~[],
+                heredocs: ~[],
+                new_heredocs: ~[],
+                quote: None,
+                word: ~"",
+                cmd_start: true,
+                dangling: false,
+                continued: false,
+            }
+        }
+
+        fn is_complete(&self) -> bool {
+            !self.continued && self.quote.is_none() && !self.dangling
+                && self.closers.len() == 0 && self.heredocs.len() == 0
+                && self.new_heredocs.len() == 0
+        }
+
+        fn scan_line(&mut self, line: &str) {
+            if self.heredocs.len() > 0 {
+                if line.trim_left_chars(&'\t') == self.heredocs[0].as_slice() {
+                    self.heredocs.shift();
+                }
+                return;
+            }
+            self.continued = false;
+            let chars: ~[char] = line.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                let c = chars[i];
+                match self.quote {
+                    Some('\'') => {
+                        if c == '\'' {
+                            self.quote = None;
+                        }
+                        self.word.push_char(c);
+                        i += 1;
+                        continue;
+                    }
+                    Some(_) => {
+                        if c == '\\' && i + 1 == chars.len() {
+                            self.continued = true;
+                            return;
+                        }
+                        if c == '\\' {
+                            self.word.push_char(c);
+                            i += 1;
+                        }
+                        else if c == '"' {
+                            self.quote = None;
+                        }
+                        self.word.push_char(chars[i]);
+                        i += 1;
+                        continue;
+                    }
+                    None => { }
+                }
+                match c {
+                    '\\' => {
+                        if i + 1 == chars.len() {
+                            self.continued = true;
+                            return;
+                        }
+                        self.word.push_char(c);
+                        self.word.push_char(chars[i + 1]);
+                        i += 2;
+                    }
+                    '\'' | '"' => {
+                        self.quote = Some(c);
+                        self.word.push_char(c);
+                        i += 1;
+                    }
+                    '#' if self.word.len() == 0 => {
+                        // The rest of the line is a comment.
+                        break;
+                    }
+                    ' ' | '\t' => {
+                        self.end_word();
+                        i += 1;
+                    }
+                    '|' | '&' | ';' | '(' | ')' | '<' | '>' => {
+                        self.end_word();
+                        i = self.operator(chars, i);
+                    }
+                    _ => {
+                        self.word.push_char(c);
+                        i += 1;
+                    }
+                }
+            }
+            if self.quote.is_some() {
+                self.word.push_char('\n');
+                return;
+            }
+            // A newline ends the command, unless an operator is waiting for
+            // its right hand side.
+            self.end_word();
+            self.cmd_start = true;
+            let started = replace(&mut self.new_heredocs, ~[]);
+            self.heredocs.push_all_move(started);
+        }
+
+        // Handle the operator starting at chars[i], returning the index just
+        // past it.
+        fn operator(&mut self, chars: &[char], i: uint) -> uint {
+            let c = chars[i];
+            let doubled = i + 1 < chars.len() && chars[i + 1] == c;
+            self.cmd_start = true;
+            self.dangling = false;
+            match c {
+                '|' => {
+                    self.dangling = true;
+                    if doubled { i + 2 } else { i + 1 }
+                }
+                '&' => {
+                    self.dangling = doubled;
+                    if doubled { i + 2 } else { i + 1 }
+                }
+                '(' => {
+                    self.closers.push(~")");
+                    i + 1
+                }
+                ')' => {
+                    // Case patterns end in a lone ')', so only close a '('
+                    // that is actually open.
+                    if self.closers.last_opt() == Some(&~")") {
+                        self.closers.pop();
+                    }
+                    i + 1
+                }
+                '<' if doubled && !(i + 2 < chars.len() && chars[i + 2] == '<') => {
+                    self.cmd_start = false;
+                    self.heredoc(chars, i + 2)
+                }
+                _ => {
+                    self.cmd_start = c == ';';
+                    i + 1
+                }
+            }
+        }
+
+        // Read the delimiter of a here-doc whose '<<' ends just before
+        // chars[i], returning the index just past it.
+        fn heredoc(&mut self, chars: &[char], i: uint) -> uint {
+            let mut i = i;
+            if i < chars.len() && chars[i] == '-' {
+                i += 1;
+            }
+            while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                i += 1;
+            }
+            let mut delimiter = ~"";
+            while i < chars.len() && !" \t;&|<>()".contains_char(chars[i]) {
+                if chars[i] != '\'' && chars[i] != '"' && chars[i] != '\\' {
+                    delimiter.push_char(chars[i]);
+                }
+                i += 1;
+            }
+            if delimiter.len() > 0 {
+                self.new_heredocs.push(delimiter);
+            }
+            i
+        }
+
+        // Finish the current word, tracking the reserved words that open and
+        // close compound commands.
+        fn end_word(&mut self) {
+            if self.word.len() == 0 {
+                return;
+            }
+            let word = self.word.clone();
+            self.word = ~"";
+            self.dangling = false;
+            if !self.cmd_start {
+                return;
+            }
+            match word.as_slice() {
+                "if" => {
+                    self.closers.push(~"fi");
+                }
+                "while" | "until" => {
+                    self.closers.push(~"done");
+                }
+                "for" | "select" => {
+                    self.closers.push(~"done");
+                    self.cmd_start = false;
+                }
+                "case" => {
+                    self.closers.push(~"esac");
+                    self.cmd_start = false;
+                }
+                "{" => {
+                    self.closers.push(~"}");
+                }
+                "then" | "else" | "elif" | "do" | "!" | "time" => {
+                }
+                "fi" | "done" | "esac" | "}" => {
+                    if self.closers.last_opt() == Some(&word) {
+                        self.closers.pop();
+                    }
+                    self.cmd_start = false;
+                }
+                _ => {
+                    self.cmd_start = false;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{is_complete, join_lines};
+
+        #[test]
+        fn open_quotes_and_trailing_operators_are_incomplete() {
+            assert!(!is_complete("echo 'open"));
+            assert!(!is_complete("echo \"a"));
+            assert!(is_complete("echo \"a\nb\""));
+            assert!(!is_complete("a |"));
+            assert!(!is_complete("a &&"));
+            assert!(!is_complete("a ||"));
+            assert!(is_complete("a &"));
+            assert!(is_complete("echo '|'"));
+            assert!(is_complete("echo a # |"));
+        }
+
+        #[test]
+        fn continuations_and_compound_commands_are_incomplete() {
+            assert!(!is_complete("echo a \\"));
+            assert!(is_complete("echo a \\\\"));
+            assert!(is_complete("echo 'a \\'"));
+            assert!(!is_complete("echo \"a \\"));
+            assert!(!is_complete("if true; then"));
+            assert!(is_complete("if true; then a; fi"));
+            assert!(!is_complete("(a"));
+            assert!(!is_complete("{ a"));
+            assert!(!is_complete("cat <<EOF\nx"));
+            assert!(is_complete("cat <<EOF\nx\nEOF"));
+        }
+
+        #[test]
+        fn join_lines_drops_backslash_newlines_outside_single_quotes() {
+            assert_eq!(join_lines("echo a \\\nb\n"), ~"echo a b\n");
+            assert_eq!(join_lines("echo \"a\\\nb\"\n"), ~"echo \"ab\"\n");
+            assert_eq!(join_lines("echo 'a\\\nb'\n"), ~"echo 'a\\\nb'\n");
+            assert_eq!(join_lines("echo a\\\\\nb\n"), ~"echo a\\\\\nb\n");
+        }
     }
 }
 
 #[allow(dead_code)]
 pub mod cmd {
     use helpers::helpers;
-    use super::pathtype::{PathType, Read, Write};
-    use std::run;
+    use super::pathtype::{PathType, Read, Write, Clobber};
+    use super::error::ParseError;
+    use commands::commands::find_program;
+    use usage::usage::{TimeMode, Formatted, Posix, Verbose};
+    use std::str::CharRange;
 
     // Represents a parsed element of a pipeline / io redirect.
     #[deriving(Clone)]
@@ -46,10 +410,163 @@ pub mod cmd {
         pipe: Option<~Cmd>,
         file: Option<PathType>,
         last : bool,
+        // The commands inside a parenthesised group, which runs in a
+        // subshell.
+        group: Option<~str>,
+        // The expression inside [[ ... ]], unexpanded: the conditional
+        // expands its own words.
+        condition: Option<~str>,
     }
+
+    // One pipeline of a command list: its text, the parsed pipeline,
+    // whether it was ended by '&' and so runs in the background, and how
+    // to report its times if it was preceded by `time`. The text is as
+    // typed; the shell expands it just before running it.
+    pub struct ListItem {
+        text: ~str,
+        cmd: ~Cmd,
+        background: bool,
+        time: Option<TimeMode>,
+        // True if the text is run as typed and expanded by whatever runs
+        // it: a builtin that reads its own text, or an and-or list sent to
+        // the background as a whole, which runs in a subshell.
+        raw: bool,
+        // After && the pipeline only runs if the one before it succeeded
+        // (Some(true)), and after || only if it failed (Some(false)).
+        run_if: Option<bool>,
+    }
+
+    // Builtins whose text isn't a pipeline: what follows the name is theirs
+    // to read, so e.g. the '|' in `coproc { a | b; }` isn't a pipe.
+    static RAW_BUILTINS: &'static [&'static str] = &["coproc", "exec", "trap"];
+
     impl Cmd {
-        pub fn new(cmd_line: &str) -> ~Cmd {
-            parse(lex(cmd_line))
+        // Parse a command line that starts on the given line of input. The
+        // line is a list of pipelines separated by ';', '&', '&&' or '||'.
+        pub fn new(cmd_line: &str, line: uint) -> Result<~[ListItem], ParseError> {
+            let mut items = ~[];
+            let mut segment : ~[Token] = ~[];
+            let mut run_if = None;
+            // Where the and-or list being read started: its first item, and
+            // its first token's offset.
+            let mut list_index = 0;
+            let mut list_column = None;
+            for token in lex(cmd_line).move_iter() {
+                if !token.is_separator() {
+                    if list_column.is_none() {
+                        list_column = Some(token.column);
+                    }
+                    segment.push(token);
+                    continue;
+                }
+                if segment.len() == 0 {
+                    return Err(ParseError::new(line, token.column, token.text,
+                                               ~"expected a command"));
+                }
+                match list_item(cmd_line, segment, line, token.column, token.text.clone()) {
+                    Ok(item) => { items.push(ListItem { run_if: run_if, ..item }); }
+                    Err(e)   => { return Err(e); }
+                }
+                segment = ~[];
+                match token.text.as_slice() {
+                    "&&" => {
+                        run_if = Some(true);
+                        continue;
+                    }
+                    "||" => {
+                        run_if = Some(false);
+                        continue;
+                    }
+                    "&" if items.len() - list_index > 1 => {
+                        // An and-or list in the background runs as a whole,
+                        // in a subshell.
+                        items.truncate(list_index);
+                        let text = cmd_line.slice(list_column.unwrap(), token.column).trim().to_owned();
+                        items.push(ListItem {
+                            cmd: Cmd::_group(text.clone()),
+                            text: text,
+                            background: true,
+                            time: None,
+                            raw: true,
+                            run_if: None,
+                        });
+                    }
+                    "&" => {
+                        items[items.len() - 1].background = true;
+                    }
+                    _ => {
+                    }
+                }
+                run_if = None;
+                list_index = items.len();
+                list_column = None;
+            }
+            if segment.len() > 0 {
+                match list_item(cmd_line, segment, line, cmd_line.len(), ~"newline") {
+                    Ok(item) => { items.push(ListItem { run_if: run_if, ..item }); }
+                    Err(e)   => { return Err(e); }
+                }
+            }
+            else if run_if.is_some() {
+                return Err(ParseError::new(line, cmd_line.len(), ~"newline",
+                                           ~"expected a command"));
+            }
+            Ok(items)
+        }
+
+        // Parse the text of a single pipeline, such as a list item's once
+        // it has been expanded.
+        pub fn pipeline(text: &str, line: uint) -> Result<~Cmd, ParseError> {
+            let tokens = lex(text);
+            match tokens.iter().find(|token| token.is_separator()) {
+                Some(token) => {
+                    return Err(ParseError::new(line, token.column, token.text.clone(),
+                                               ~"expected a single pipeline"));
+                }
+                None => {
+                }
+            }
+            parse(tokens, line, text.len(), ~"newline")
+        }
+
+        // A parenthesised group, given the text between the parentheses.
+        fn _group(inner: ~str) -> ~Cmd {
+            ~Cmd {
+                program: ~"(",
+                argv: ~[],
+                pipe: None,
+                file: None,
+                last: true,
+                group: Some(inner),
+                condition: None,
+            }
+        }
+
+        // A [[ ... ]] conditional, given the text between the brackets.
+        fn _condition(inner: ~str) -> ~Cmd {
+            ~Cmd {
+                program: ~"[[",
+                argv: ~[],
+                pipe: None,
+                file: None,
+                last: true,
+                group: None,
+                condition: Some(inner),
+            }
+        }
+
+        // True for a lone command with no pipes, redirections or grouping,
+        // which is what a builtin can be. A lone conditional counts.
+        pub fn is_simple(&self) -> bool {
+            self.pipe.is_none() && self.file.is_none() && self.group.is_none()
+        }
+
+        // The byte ranges of the [[ ... ]] conditionals and ( ... ) groups
+        // in a command line, which the shell leaves for the conditional or
+        // the subshell to expand.
+        pub fn unexpanded(cmd_line: &str) -> ~[(uint, uint)] {
+            lex(cmd_line).iter().filter(|token| token.is_condition() || token.is_group())
+                .map(|token| (token.column, token.column + token.text.len())).collect()
         }
 
         #[allow(dead_code)]
@@ -68,12 +585,15 @@ pub mod cmd {
                 pipe: None,
                 file: None,
                 last: true,
+                group: None,
+                condition: None,
             }
         }
 
-        pub fn exists(&mut self) -> bool {
-            let ret = run::process_output("which", [self.program.to_owned()]);
-            ret.expect("exit code error.").status.success()
+        // True if the program can be found on path_var, the shell's $PATH
+        // (which may not be the one in its environment).
+        pub fn exists(&self, path_var: &str) -> bool {
+            find_program(self.program, path_var).is_some()
         }
 
         // Set the path at the bottom of the pipe chain. This means e.g. in order
@@ -90,6 +610,8 @@ pub mod cmd {
                         pipe: Some(elem.set_path(path)),
                         file: self.file.clone(),
                         last: self.last,
+                        group: self.group.clone(),
+                        condition: self.condition.clone(),
                     }
                 }
                 None => {
@@ -99,6 +621,8 @@ pub mod cmd {
                         pipe: self.pipe.clone(),
                         file: Some(path),
                         last: self.last,
+                        group: self.group.clone(),
+                        condition: self.condition.clone(),
                     }
                 }
             }
@@ -117,6 +641,8 @@ pub mod cmd {
                         pipe: Some(elem.set_pipe(pipe)),
                         file: self.file.clone(),
                         last: false,
+                        group: self.group.clone(),
+                        condition: self.condition.clone(),
                     }
                 }
                 None => {
@@ -126,6 +652,8 @@ pub mod cmd {
                         pipe: Some(pipe), 
                         file: self.file.clone(),
                         last: false,
+                        group: self.group.clone(),
+                        condition: self.condition.clone(),
                     }
                 }
             }
@@ -151,77 +679,458 @@ pub mod cmd {
         }
     }
 
-    // Split the input up into words.
-    fn lex(cmd_line: &str) -> ~[~str] {
-        let breakchars = ~['>', '<', '|'];
-        let mut slices : ~[~str] = ~[];
-        let mut last = 0;
-        let mut current = 0;
-        for i in range(0, cmd_line.len()) {
-            // This is a special character.
-            if breakchars.contains(&cmd_line.char_at(i)) && i != 0 {
-                if last != 0 {
-                    slices.push(cmd_line.slice(last+1, current).trim().to_owned());
+    // A lexed command or operator, and the byte offset it starts at.
+    #[deriving(Clone)]
+    struct Token {
+        text: ~str,
+        column: uint,
+    }
+
+    impl Token {
+        fn is_operator(&self) -> bool {
+            self.text == ~">" || self.text == ~">|" || self.text == ~"<" || self.text == ~"|"
+                || self.is_duplication()
+        }
+
+        // The fd duplications >&, <& and &>, which only exec understands.
+        fn is_duplication(&self) -> bool {
+            self.text == ~">&" || self.text == ~"<&" || self.text == ~"&>"
+        }
+
+        fn is_separator(&self) -> bool {
+            self.text == ~";" || self.text == ~"&" || self.text == ~"&&" || self.text == ~"||"
+        }
+
+        fn is_group(&self) -> bool {
+            self.text.starts_with("(")
+        }
+
+        fn is_condition(&self) -> bool {
+            opens_condition(self.text, 0)
+        }
+    }
+
+    // Split the input up into commands and operators. The line is walked a
+    // character at a time, so start and i are always on character
+    // boundaries and slicing at them is safe for multi-byte UTF-8. Operators
+    // inside quotes are just text, and a '(' at the start of a command
+    // takes everything up to its matching ')' as a single group token, as
+    // a '[[' does up to its ']]'. Operators inside a '{ ... }' body, like
+    // coproc's, are text too.
+    fn lex(cmd_line: &str) -> ~[Token] {
+        let breakchars = ~['>', '<', '|', '&', ';'];
+        let mut tokens : ~[Token] = ~[];
+        let mut start = 0;
+        let mut i = 0;
+        let mut quote : Option<char> = None;
+        while i < cmd_line.len() {
+            let CharRange { ch, next } = cmd_line.char_range_at(i);
+            match quote {
+                // A backslash keeps the next character from ending a quote
+                // or being an operator, except inside single quotes.
+                _ if ch == '\\' && quote != Some('\'') && next < cmd_line.len() => {
+                    i = cmd_line.char_range_at(next).next;
+                    continue;
+                }
+                Some(q) => {
+                    if ch == q {
+                        quote = None;
+                    }
+                }
+                None if ch == '"' || ch == '\'' => {
+                    quote = Some(ch);
                 }
-                else {
-                    slices.push(cmd_line.slice_to(current).trim().to_owned());
+                None if ch == '(' && cmd_line.slice(start, i).trim() == "" => {
+                    let end = match matching_bracket(cmd_line, i) {
+                        Some(close) => { close + 1 }
+                        None        => { cmd_line.len() }
+                    };
+                    tokens.push(Token {
+                        text: cmd_line.slice(i, end).to_owned(),
+                        column: i,
+                    });
+                    start = end;
+                    i = end;
+                    continue;
+                }
+                None if opens_condition(cmd_line, i) && cmd_line.slice(start, i).trim() == "" => {
+                    let end = closing_brackets(cmd_line, i).unwrap_or(cmd_line.len());
+                    tokens.push(Token {
+                        text: cmd_line.slice(i, end).to_owned(),
+                        column: i,
+                    });
+                    start = end;
+                    i = end;
+                    continue;
+                }
+                None if opens_body(cmd_line, i) && matching_bracket(cmd_line, i).is_some() => {
+                    i = matching_bracket(cmd_line, i).unwrap() + 1;
+                    continue;
+                }
+                // The '&' of >&, <& and &> belongs to the redirection; it
+                // doesn't end the pipeline.
+                None if ch == '&' && tokens.len() > 0 && start == i
+                        && tokens.last().column + 1 == i
+                        && (tokens.last().text == ~">" || tokens.last().text == ~"<") => {
+                    tokens[tokens.len() - 1].text.push_char('&');
+                    start = next;
+                }
+                None if ch == '&' && cmd_line.slice_from(next).starts_with(">") => {
+                    push_word(&mut tokens, cmd_line, start, i);
+                    tokens.push(Token {
+                        text: ~"&>",
+                        column: i,
+                    });
+                    start = next + 1;
+                    i = next + 1;
+                    continue;
+                }
+                // This is a special character, or the first of &&, || or >|.
+                None if breakchars.contains(&ch) => {
+                    push_word(&mut tokens, cmd_line, start, i);
+                    let following = if next < cmd_line.len() { cmd_line.char_at(next) } else { ' ' };
+                    let end = match (ch, following) {
+                        ('&', '&') | ('|', '|') | ('>', '|') => { next + 1 }
+                        _                                    => { next }
+                    };
+                    tokens.push(Token {
+                        text: cmd_line.slice(i, end).to_owned(),
+                        column: i,
+                    });
+                    start = end;
+                    i = end;
+                    continue;
+                }
+                None => {
                 }
-                slices.push(cmd_line.slice(i, i+1).to_owned());
-                last = i;
             }
-            current += 1;
+            i = next;
         }
-        if last == 0 {
-            slices.push(cmd_line.trim().to_owned());
-        }
-        else {
-            slices.push(cmd_line.slice_from(last+1).trim().to_owned());
+        push_word(&mut tokens, cmd_line, start, cmd_line.len());
+        tokens
+    }
+
+    // True if a '{' word, which opens a body, starts at cmd_line[i].
+    fn opens_body(cmd_line: &str, i: uint) -> bool {
+        let before = cmd_line.slice_to(i);
+        let rest = cmd_line.slice_from(i);
+        rest.starts_with("{") && rest.len() > 1 && rest.char_at(1).is_whitespace()
+            && (before.len() == 0 || before.char_at_reverse(before.len()).is_whitespace())
+    }
+
+    // The byte offset of the ')' or '}' matching the '(' or '{' at open, if
+    // there is one.
+    fn matching_bracket(cmd_line: &str, open: uint) -> Option<uint> {
+        let (opener, closer) = if cmd_line.char_at(open) == '{' { ('{', '}') } else { ('(', ')') };
+        let mut depth = 0;
+        let mut quote : Option<char> = None;
+        let mut i = open;
+        while i < cmd_line.len() {
+            let CharRange { ch, next } = cmd_line.char_range_at(i);
+            match quote {
+                Some(q) => {
+                    if ch == q {
+                        quote = None;
+                    }
+                }
+                None => {
+                    if ch == '"' || ch == '\'' {
+                        quote = Some(ch);
+                    }
+                    else if ch == opener {
+                        depth += 1;
+                    }
+                    else if ch == closer {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i);
+                        }
+                    }
+                }
+            }
+            i = next;
         }
-        slices
+        None
     }
 
-    // Parse the lexxed input into a (recursive linked by .pipe field) 
-    // list of Cmds.
-    fn parse(words: ~[~str]) -> ~Cmd {
-        let breakchars = ~['>', '<', '|'];
-        let mut slices : ~[~Cmd] = ~[];
-        let mut in_file = false;
-        let mut out_file = false;
-        let mut pipe = false;
-        for i in range(0, words.len()) {
-            if words[i].len() == 1 
-                    && breakchars.contains(&words[i].char_at(0)) {
-                if words[i] == ~">" {
-                    out_file = true;
+    // True if a '[[' word starts at cmd_line[i].
+    fn opens_condition(cmd_line: &str, i: uint) -> bool {
+        let rest = cmd_line.slice_from(i);
+        rest.starts_with("[[") && (rest.len() == 2 || rest.char_at(2).is_whitespace())
+    }
+
+    // The byte offset just past the ']]' word closing the '[[' at open, if
+    // there is one.
+    fn closing_brackets(cmd_line: &str, open: uint) -> Option<uint> {
+        let mut quote : Option<char> = None;
+        let mut i = open + 2;
+        let mut prev = ' ';
+        while i < cmd_line.len() {
+            let CharRange { ch, next } = cmd_line.char_range_at(i);
+            match quote {
+                Some(q) => {
+                    if ch == q {
+                        quote = None;
+                    }
                 }
-                if words[i] == ~"<" {
-                    in_file = true;
+                None if ch == '"' || ch == '\'' => {
+                    quote = Some(ch);
                 }
-                if words[i] == ~"|" {
-                    pipe = true;
+                None if ch == ']' && prev.is_whitespace()
+                        && cmd_line.slice_from(i).starts_with("]]") => {
+                    let end = i + 2;
+                    if end == cmd_line.len() || cmd_line.char_at(end).is_whitespace()
+                            || ";&|<>)".contains_char(cmd_line.char_at(end)) {
+                        return Some(end);
+                    }
                 }
+                None => {
+                }
+            }
+            prev = ch;
+            i = next;
+        }
+        None
+    }
+
+    // Push the text between start and end as a token, unless it's blank.
+    fn push_word(tokens: &mut ~[Token], cmd_line: &str, start: uint, end: uint) {
+        let word = cmd_line.slice(start, end);
+        let trimmed = word.trim_left();
+        if trimmed.len() > 0 {
+            tokens.push(Token {
+                text: trimmed.trim_right().to_owned(),
+                column: start + word.len() - trimmed.len(),
+            });
+        }
+    }
+
+    // Make the list item for the tokens of one pipeline, which ends at end
+    // with end_token. It runs in the foreground until told otherwise.
+    fn list_item(cmd_line: &str, segment: ~[Token], line: uint, end: uint, end_token: ~str)
+            -> Result<ListItem, ParseError> {
+        let mut segment = segment;
+        let time = match take_time(&mut segment, line, end, end_token.as_slice()) {
+            Ok(time) => { time }
+            Err(e)   => { return Err(e); }
+        };
+        let start = segment[0].column;
+        let text = cmd_line.slice(start, end).trim().to_owned();
+        let name = text.splitn(' ', 1).nth(0).unwrap().to_owned();
+        if RAW_BUILTINS.iter().any(|builtin| *builtin == name.as_slice()) {
+            return Ok(ListItem {
+                cmd: Cmd::_new(text.clone()),
+                text: text,
+                background: false,
+                time: time,
+                raw: true,
+                run_if: None,
+            });
+        }
+        let cmd = match parse(segment, line, end, end_token) {
+            Ok(cmd) => { cmd }
+            Err(e)  => { return Err(e); }
+        };
+        Ok(ListItem {
+            text: text,
+            cmd: cmd,
+            background: false,
+            time: time,
+            raw: false,
+            run_if: None,
+        })
+    }
+
+    // Take a leading `time`, and its -p or -v option, off the first word
+    // of a pipeline. The pipeline ends at end, with end_token.
+    fn take_time(segment: &mut ~[Token], line: uint, end: uint, end_token: &str)
+            -> Result<Option<TimeMode>, ParseError> {
+        let mut time = None;
+        loop {
+            if segment.len() == 0 || segment[0].is_group() || segment[0].is_condition()
+                    || segment[0].is_operator() {
+                break;
+            }
+            let text = segment[0].text.clone();
+            let word = text.splitn(' ', 1).nth(0).unwrap();
+            let mode = match (time, word) {
+                (None, "time")           => { Formatted }
+                (Some(Formatted), "-p")  => { Posix }
+                (Some(Formatted), "-v")  => { Verbose }
+                _                        => { break; }
+            };
+            time = Some(mode);
+            let rest = text.slice_from(word.len()).trim_left();
+            if rest.len() == 0 {
+                segment.shift();
             }
             else {
-                if out_file {
-                    let cmd = slices.pop();
-                    slices.push(cmd.set_path(PathType::new(words[i].to_owned(), Write)));
-                    out_file = false;
+                segment[0].column += text.len() - rest.len();
+                segment[0].text = rest.to_owned();
+            }
+        }
+        if time.is_some() && (segment.len() == 0 || segment[0].is_operator()) {
+            let (column, token) = match segment.iter().nth(0) {
+                Some(token) => { (token.column, token.text.clone()) }
+                None        => { (end, end_token.to_owned()) }
+            };
+            return Err(ParseError::new(line, column, token, ~"expected a command"));
+        }
+        Ok(time)
+    }
+
+    // Make the Cmd for a command token: a group, a conditional or a simple
+    // command.
+    fn command(token: Token, line: uint) -> Result<~Cmd, ParseError> {
+        if token.is_condition() {
+            let text = token.text.as_slice();
+            if text.len() < 5 || !text.ends_with("]]")
+                    || !text.char_at(text.len() - 3).is_whitespace() {
+                return Err(ParseError::new(line, token.column + text.len(), ~"newline",
+                                           ~"expected ']]'"));
+            }
+            return Ok(Cmd::_condition(text.slice(2, text.len() - 2).trim().to_owned()));
+        }
+        if !token.is_group() {
+            return Ok(Cmd::_new(token.text));
+        }
+        if !token.text.ends_with(")") {
+            return Err(ParseError::new(line, token.column + token.text.len(), ~"newline",
+                                       ~"expected ')'"));
+        }
+        let inner = token.text.slice(1, token.text.len() - 1).trim().to_owned();
+        Ok(Cmd::_group(inner))
+    }
+
+    // Parse the lexxed input of one pipeline into a (recursive linked by
+    // .pipe field) list of Cmds. The pipeline ends at end, with end_token
+    // (';', '&' or the end of the line).
+    fn parse(tokens: ~[Token], line: uint, end: uint, end_token: ~str)
+            -> Result<~Cmd, ParseError> {
+        let mut cmd : Option<~Cmd> = None;
+        // The operator waiting for its right hand side, if any.
+        let mut pending : Option<Token> = None;
+        for token in tokens.move_iter() {
+            if token.is_operator() {
+                if cmd.is_none() || pending.is_some() {
+                    return Err(ParseError::new(line, token.column, token.text,
+                                               expected_after(&pending)));
                 }
-                else if in_file {
-                    let cmd = slices.pop();
-                    slices.push(cmd.set_path(PathType::new(words[i].to_owned(), Read)));
-                    in_file = false;
+                pending = Some(token);
+                continue;
+            }
+            cmd = match (cmd, pending.take()) {
+                (None, _) => {
+                    match command(token, line) {
+                        Ok(cmd) => { Some(cmd) }
+                        Err(e)  => { return Err(e); }
+                    }
                 }
-                else if pipe {
-                    let cmd = slices.pop();
-                    slices.push(cmd.set_pipe(Cmd::_new(words[i].to_owned())));
-                    pipe = false;
+                (Some(cmd), Some(op)) => {
+                    if op.is_duplication() {
+                        return Err(ParseError::new(line, op.column, op.text,
+                                                   ~"only exec can redirect to a file descriptor"));
+                    }
+                    else if op.text == ~">" {
+                        Some(cmd.set_path(PathType::new(file_name(token.text), Write)))
+                    }
+                    else if op.text == ~">|" {
+                        Some(cmd.set_path(PathType::new(file_name(token.text), Clobber)))
+                    }
+                    else if op.text == ~"<" {
+                        Some(cmd.set_path(PathType::new(file_name(token.text), Read)))
+                    }
+                    else {
+                        match command(token, line) {
+                            Ok(right) => { Some(cmd.set_pipe(right)) }
+                            Err(e)    => { return Err(e); }
+                        }
+                    }
                 }
-                else {
-                    slices.push(Cmd::_new(words[i].to_owned()));
+                (Some(_), None) => {
+                    // Only a group or a conditional can be followed by a word
+                    // with no operator between them, as in `(a) b`.
+                    let word = token.text.splitn(' ', 1).nth(0).unwrap().to_owned();
+                    let expected = ~"expected '|', a redirection or the end of the command";
+                    return Err(ParseError::new(line, token.column, word, expected));
                 }
+            };
+        }
+        if pending.is_some() || cmd.is_none() {
+            return Err(ParseError::new(line, end, end_token,
+                                       expected_after(&pending)));
+        }
+        Ok(cmd.unwrap())
+    }
+
+    // The file a redirection names, with its quotes removed.
+    fn file_name(text: &str) -> ~str {
+        helpers::split_words(text).connect(" ")
+    }
+
+    // Describe what may follow an operator (or start a line, if None).
+    fn expected_after(op: &Option<Token>) -> ~str {
+        match *op {
+            Some(ref op) if op.text != ~"|" => { ~"expected a file name" }
+            _ => { ~"expected a command" }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::Cmd;
+
+        // The column and token of the syntax error in line, which mustn't
+        // parse.
+        fn error_at(line: &str) -> (uint, ~str) {
+            match Cmd::new(line, 1) {
+                Ok(_)  => { fail!("`{:s}' parsed", line) }
+                Err(e) => { (e.column, e.token) }
             }
         }
-        slices[0]
+
+        #[test]
+        fn malformed_lines_are_errors_at_the_right_column() {
+            assert_eq!(error_at("|"), (0, ~"|"));
+            assert_eq!(error_at("a ||"), (4, ~"newline"));
+            assert_eq!(error_at("a >"), (3, ~"newline"));
+            assert_eq!(error_at(";;"), (0, ~";"));
+            assert_eq!(error_at("a | | b"), (4, ~"|"));
+            assert_eq!(error_at("a && ; b"), (5, ~";"));
+            assert_eq!(error_at("a > < b"), (4, ~"<"));
+            assert_eq!(error_at("(a"), (2, ~"newline"));
+            assert_eq!(error_at("[[ x"), (4, ~"newline"));
+            assert_eq!(error_at("time"), (4, ~"newline"));
+            assert_eq!(error_at("(a) b"), (4, ~"b"));
+            assert_eq!(error_at("[[ x ]] y"), (8, ~"y"));
+        }
+
+        #[test]
+        fn errors_say_what_was_expected() {
+            match Cmd::new("a >", 1) {
+                Err(e) => { assert_eq!(e.expected, ~"expected a file name"); }
+                Ok(_)  => { fail!("`a >' parsed"); }
+            }
+            match Cmd::new("a |", 1) {
+                Err(e) => { assert_eq!(e.expected, ~"expected a command"); }
+                Ok(_)  => { fail!("`a |' parsed"); }
+            }
+        }
+
+        #[test]
+        fn word_after_group_is_an_error() {
+            assert_eq!(error_at("(a) b"), (4, ~"b"));
+            assert_eq!(error_at("(make) 2>&1 | tee log"), (7, ~"2"));
+            assert_eq!(error_at("echo x; (a) b c"), (12, ~"b"));
+            assert!(Cmd::new("(a) > out | cat", 1).is_ok());
+        }
+
+        #[test]
+        fn word_after_condition_is_an_error() {
+            assert_eq!(error_at("[[ x ]] 2>/dev/null"), (8, ~"2"));
+            assert_eq!(error_at("[[ a ]] foo"), (8, ~"foo"));
+            assert!(Cmd::new("[[ a ]] > /dev/null", 1).is_ok());
+            assert!(Cmd::new("[[ a < b ]] && [[ (x) ]]", 1).is_ok());
+        }
     }
 }
@@ -0,0 +1,569 @@
+#[allow(dead_code)]
+pub mod printf {
+    // Backslash escapes and format strings for the echo and printf
+    // builtins. Numbers are formatted by the C library's snprintf, so flags,
+    // widths and precisions mean just what they do in C, and %(fmt)T times
+    // by strftime.
+    use std::libc::{c_char, c_int, c_long, size_t};
+    use std::c_str::ToCStr;
+    use std::num::from_str_radix;
+    use std::char;
+    use std::ptr;
+    use std::str;
+    use std::vec;
+    use extra::time::get_time;
+
+    // struct tm from <time.h> on Linux.
+    struct Tm {
+        tm_sec    : c_int,
+        tm_min    : c_int,
+        tm_hour   : c_int,
+        tm_mday   : c_int,
+        tm_mon    : c_int,
+        tm_year   : c_int,
+        tm_wday   : c_int,
+        tm_yday   : c_int,
+        tm_isdst  : c_int,
+        tm_gmtoff : c_long,
+        tm_zone   : *c_char,
+    }
+
+    extern {
+        fn snprintf(buf: *mut c_char, len: size_t, format: *c_char, ...) -> c_int;
+        fn strftime(buf: *mut c_char, len: size_t, format: *c_char, tm: *Tm) -> size_t;
+        fn localtime_r(time: *c_long, tm: *mut Tm) -> *mut Tm;
+    }
+
+    // A value for snprintf.
+    #[deriving(Clone)]
+    enum CArg {
+        Int(i64),
+        Unsigned(u64),
+        Float(f64),
+    }
+
+    unsafe fn call_snprintf(buf: *mut c_char, len: size_t, format: *c_char, arg: CArg) -> c_int {
+        match arg {
+            Int(value)      => { snprintf(buf, len, format, value) }
+            Unsigned(value) => { snprintf(buf, len, format, value) }
+            Float(value)    => { snprintf(buf, len, format, value) }
+        }
+    }
+
+    // Format one value with a C conversion spec like "%-8.3f".
+    fn c_format(spec: &str, arg: CArg) -> ~str {
+        spec.with_c_str(|format| unsafe {
+            let len = call_snprintf(ptr::mut_null(), 0, format, arg.clone()) as uint;
+            let mut buf = vec::from_elem(len + 1, 0u8);
+            call_snprintf(buf.as_mut_ptr() as *mut c_char, (len + 1) as size_t, format, arg);
+            buf.truncate(len);
+            str::from_utf8_owned(buf)
+        })
+    }
+
+    // Format a time, in seconds since the epoch, with strftime.
+    fn format_time(format: &str, time: i64) -> ~str {
+        let time = time as c_long;
+        let mut tm = Tm {
+            tm_sec: 0, tm_min: 0, tm_hour: 0, tm_mday: 0, tm_mon: 0, tm_year: 0,
+            tm_wday: 0, tm_yday: 0, tm_isdst: 0, tm_gmtoff: 0, tm_zone: ptr::null(),
+        };
+        if unsafe { localtime_r(&time, &mut tm) }.is_null() {
+            return ~"";
+        }
+        // strftime gives 0 both when the buffer is too small and when the
+        // time formats to nothing, so give up once the buffer is large.
+        let mut len = 256;
+        while len <= 65536 {
+            let mut buf = vec::from_elem(len, 0u8);
+            let written = format.with_c_str(|format| unsafe {
+                strftime(buf.as_mut_ptr() as *mut c_char, len as size_t, format, &tm)
+            }) as uint;
+            if written > 0 {
+                buf.truncate(written);
+                return str::from_utf8_owned_opt(buf).unwrap_or(~"");
+            }
+            len *= 4;
+        }
+        ~""
+    }
+
+    // Read up to max digits in radix from chars[start], returning their
+    // value and how many there were.
+    fn digits(chars: &[char], start: uint, max: uint, radix: uint) -> (u32, uint) {
+        let mut value = 0u32;
+        let mut count = 0;
+        while count < max && start + count < chars.len() {
+            match chars[start + count].to_digit(radix) {
+                Some(digit) => { value = value * radix as u32 + digit as u32; }
+                None        => { break; }
+            }
+            count += 1;
+        }
+        (value, count)
+    }
+
+    // The escape starting at chars[i], just after a backslash: what it
+    // stands for (None for \c, which ends the output) and how many
+    // characters it took. echo and %b write octal escapes as \0nnn, a
+    // printf format as \nnn. Unknown escapes are left as they are.
+    fn escape(chars: &[char], i: uint, echo: bool) -> (Option<~str>, uint) {
+        if i == chars.len() {
+            return (Some(~"\\"), 0);
+        }
+        let simple = match chars[i] {
+            'a'  => { Some('\x07') }
+            'b'  => { Some('\x08') }
+            'e' | 'E' => { Some('\x1b') }
+            'f'  => { Some('\x0c') }
+            'n'  => { Some('\n') }
+            'r'  => { Some('\r') }
+            't'  => { Some('\t') }
+            'v'  => { Some('\x0b') }
+            '\\' => { Some('\\') }
+            '"' if !echo => { Some('"') }
+            '\'' if !echo => { Some('\'') }
+            _    => { None }
+        };
+        match simple {
+            Some(c) => { return (Some(c.to_str()), 1); }
+            None    => { }
+        }
+        let (value, taken) = match chars[i] {
+            'c' => { return (None, 1); }
+            '0' if echo => {
+                let (value, count) = digits(chars, i + 1, 3, 8);
+                (Some(value), count + 1)
+            }
+            '0'..'7' if !echo => {
+                let (value, count) = digits(chars, i, 3, 8);
+                (Some(value), count)
+            }
+            'x' | 'u' | 'U' => {
+                let max = match chars[i] { 'x' => { 2 } 'u' => { 4 } _ => { 8 } };
+                let (value, count) = digits(chars, i + 1, max, 16);
+                if count == 0 { (None, 0) } else { (Some(value), count + 1) }
+            }
+            _ => {
+                (None, 0)
+            }
+        };
+        match value.and_then(|value| char::from_u32(value)) {
+            Some(c) => { (Some(c.to_str()), taken) }
+            None    => { (Some(format!("\\\\{}", chars[i])), 1) }
+        }
+    }
+
+    // Expand the backslash escapes of echo -e and printf's %b. Returns the
+    // text, and true if a \c said to stop there.
+    pub fn echo_escapes(text: &str) -> (~str, bool) {
+        let chars: ~[char] = text.chars().collect();
+        let mut out = ~"";
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] != '\\' {
+                out.push_char(chars[i]);
+                i += 1;
+                continue;
+            }
+            match escape(chars, i + 1, true) {
+                (Some(text), taken) => {
+                    out.push_str(text);
+                    i += taken + 1;
+                }
+                (None, _) => {
+                    return (out, true);
+                }
+            }
+        }
+        (out, false)
+    }
+
+    // Quote text so the shell would read it back as one word, the way
+    // printf's %q does.
+    pub fn quote(text: &str) -> ~str {
+        if text.len() == 0 {
+            return ~"''";
+        }
+        let mut out = ~"";
+        if text.chars().any(|c| c < ' ' || c == '\x7f') {
+            out.push_str("$'");
+            for c in text.chars() {
+                match c {
+                    '\n'   => { out.push_str("\\n"); }
+                    '\t'   => { out.push_str("\\t"); }
+                    '\r'   => { out.push_str("\\r"); }
+                    '\x1b' => { out.push_str("\\E"); }
+                    '\''   => { out.push_str("\\'"); }
+                    '\\'   => { out.push_str("\\\\"); }
+                    _ if c < ' ' || c == '\x7f' => {
+                        out.push_str(format!("\\\\{:03o}", c as uint));
+                    }
+                    _      => { out.push_char(c); }
+                }
+            }
+            out.push_char('\'');
+            return out;
+        }
+        for c in text.chars() {
+            if " \t'\"\\|&;()<>$`*?[]#~=%{},!^".contains_char(c) {
+                out.push_char('\\');
+            }
+            out.push_char(c);
+        }
+        out
+    }
+
+    // A format run over the arguments: its output, complaints about the
+    // arguments, and whether it had to stop early.
+    pub struct Printed {
+        output : ~str,
+        errors : ~[~str],
+        failed : bool,
+    }
+
+    // Format args with a printf format. The format is used again for as
+    // long as arguments are left over; missing ones count as empty
+    // strings or zero. start_time is what %(fmt)T shows for -2.
+    pub fn printf(format: &str, args: &[~str], start_time: i64) -> Printed {
+        let mut state = Formatter {
+            format: format.chars().collect(),
+            args: args,
+            next: 0,
+            printed: Printed { output: ~"", errors: ~[], failed: false },
+            start_time: start_time,
+        };
+        loop {
+            let first = state.next;
+            if !state.run() {
+                break;
+            }
+            if state.next == first || state.next >= args.len() {
+                break;
+            }
+        }
+        state.printed
+    }
+
+    struct Formatter<'a> {
+        format     : ~[char],
+        args       : &'a [~str],
+        // The next argument to use.
+        next       : uint,
+        printed    : Printed,
+        start_time : i64,
+    }
+
+    impl<'a> Formatter<'a> {
+        fn take(&mut self) -> Option<~str> {
+            if self.next < self.args.len() {
+                self.next += 1;
+                Some(self.args[self.next - 1].clone())
+            }
+            else {
+                None
+            }
+        }
+
+        fn complain(&mut self, message: ~str) {
+            self.printed.errors.push(message);
+        }
+
+        // Go through the format once. Returns false if the output should
+        // stop here.
+        fn run(&mut self) -> bool {
+            let mut i = 0;
+            while i < self.format.len() {
+                let c = self.format[i];
+                i += 1;
+                if c == '\\' {
+                    match escape(self.format, i, false) {
+                        (Some(text), taken) => {
+                            self.printed.output.push_str(text);
+                            i += taken;
+                        }
+                        (None, _) => {
+                            // \c is only special in %b.
+                            self.printed.output.push_str("\\c");
+                            i += 1;
+                        }
+                    }
+                    continue;
+                }
+                if c != '%' {
+                    self.printed.output.push_char(c);
+                    continue;
+                }
+                match self.conversion(i) {
+                    Some(end) => { i = end; }
+                    None      => { return false; }
+                }
+            }
+            true
+        }
+
+        // Carry out the conversion whose spec starts at format[start], just
+        // after the %. Returns where the format carries on, or None if the
+        // output should stop.
+        fn conversion(&mut self, start: uint) -> Option<uint> {
+            let mut i = start;
+            let mut flags = ~"";
+            while i < self.format.len() && "-+ #0".contains_char(self.format[i]) {
+                flags.push_char(self.format[i]);
+                i += 1;
+            }
+            let width = self.number_or_star(&mut i);
+            let precision = if i < self.format.len() && self.format[i] == '.' {
+                i += 1;
+                Some(self.number_or_star(&mut i).unwrap_or(0))
+            }
+            else {
+                None
+            };
+            // Negative widths from * mean left justification.
+            let width = match width {
+                Some(width) if width < 0 => {
+                    flags.push_char('-');
+                    Some(-width)
+                }
+                other => { other }
+            };
+            if i == self.format.len() {
+                self.complain(~"gash: printf: `%': missing format character");
+                self.printed.failed = true;
+                return None;
+            }
+            let conv = self.format[i];
+            i += 1;
+            let mut spec = format!("%{:s}", flags);
+            match width {
+                Some(width) => { spec.push_str(width.to_str()); }
+                None        => { }
+            }
+            match precision {
+                Some(precision) => { spec.push_str(format!(".{}", precision)); }
+                None            => { }
+            }
+            let text = match conv {
+                '%' => {
+                    ~"%"
+                }
+                's' => {
+                    let arg = self.take().unwrap_or(~"");
+                    pad(arg, flags, width, precision)
+                }
+                'q' => {
+                    let arg = self.take().unwrap_or(~"");
+                    pad(quote(arg), flags, width, precision)
+                }
+                'c' => {
+                    let arg = self.take().unwrap_or(~"");
+                    let first = arg.chars().next().map(|c| c.to_str()).unwrap_or(~"");
+                    pad(first, flags, width, None)
+                }
+                'b' => {
+                    let arg = self.take().unwrap_or(~"");
+                    let (text, stop) = echo_escapes(arg);
+                    let text = pad(text, flags, width, precision);
+                    if stop {
+                        self.printed.output.push_str(text);
+                        return None;
+                    }
+                    text
+                }
+                'd' | 'i' => {
+                    let arg = self.take().unwrap_or(~"");
+                    let value = self.integer(arg);
+                    c_format(spec + "lld", Int(value))
+                }
+                'o' | 'u' | 'x' | 'X' => {
+                    let arg = self.take().unwrap_or(~"");
+                    let value = self.integer(arg);
+                    c_format(format!("{:s}ll{}", spec, conv), Unsigned(value as u64))
+                }
+                'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+                    let arg = self.take().unwrap_or(~"");
+                    let value = self.float(arg);
+                    c_format(format!("{:s}{}", spec, conv), Float(value))
+                }
+                '(' => {
+                    let close = match self.format.slice_from(i).position_elem(&')') {
+                        Some(close) => { i + close }
+                        None => {
+                            self.complain(~"gash: printf: `(': missing closing parenthesis");
+                            self.printed.failed = true;
+                            return None;
+                        }
+                    };
+                    if close + 1 == self.format.len() || self.format[close + 1] != 'T' {
+                        self.complain(~"gash: printf: `(': invalid time format specification");
+                        self.printed.failed = true;
+                        return None;
+                    }
+                    let time_format = str::from_chars(self.format.slice(i, close));
+                    i = close + 2;
+                    // -1, or no argument, is now; -2 is when the shell
+                    // started.
+                    let time = match self.take() {
+                        Some(arg) => { self.integer(arg) }
+                        None      => { -1 }
+                    };
+                    let time = match time {
+                        -1 => { get_time().sec }
+                        -2 => { self.start_time }
+                        _  => { time }
+                    };
+                    pad(format_time(time_format, time), flags, width, precision)
+                }
+                _ => {
+                    self.complain(format!("gash: printf: `{}': invalid format character", conv));
+                    self.printed.failed = true;
+                    return None;
+                }
+            };
+            self.printed.output.push_str(text);
+            Some(i)
+        }
+
+        // A width or precision at format[*i]: digits, or * for the next
+        // argument.
+        fn number_or_star(&mut self, i: &mut uint) -> Option<i64> {
+            if *i < self.format.len() && self.format[*i] == '*' {
+                *i += 1;
+                let arg = self.take().unwrap_or(~"");
+                return Some(self.integer(arg));
+            }
+            let (value, count) = digits(self.format, *i, 9, 10);
+            *i += count;
+            if count > 0 { Some(value as i64) } else { None }
+        }
+
+        // An integer argument: decimal, octal with a leading 0, hex with 0x,
+        // or 'c for the code of the character c. Anything unreadable is
+        // complained about, and counts as what could be read of it.
+        fn integer(&mut self, arg: &str) -> i64 {
+            let text = arg.trim();
+            if text.starts_with("'") || text.starts_with("\"") {
+                return text.chars().nth(1).map(|c| c as u32 as i64).unwrap_or(0);
+            }
+            let (negative, digits) = if text.starts_with("-") {
+                (true, text.slice_from(1))
+            }
+            else if text.starts_with("+") {
+                (false, text.slice_from(1))
+            }
+            else {
+                (false, text)
+            };
+            let (radix, digits) = if digits.starts_with("0x") || digits.starts_with("0X") {
+                (16, digits.slice_from(2))
+            }
+            else if digits.starts_with("0") && digits.len() > 1 {
+                (8, digits.slice_from(1))
+            }
+            else {
+                (10, digits)
+            };
+            let end = digits.find(|c: char| c.to_digit(radix).is_none()).unwrap_or(digits.len());
+            if end < digits.len() || (text.len() > 0 && end == 0 && radix != 8) {
+                self.complain(format!("gash: printf: {:s}: invalid number", arg));
+                self.printed.failed = true;
+            }
+            let value = from_str_radix::<i64>(digits.slice_to(end), radix).unwrap_or(0);
+            if negative { -value } else { value }
+        }
+
+        fn float(&mut self, arg: &str) -> f64 {
+            let text = arg.trim();
+            if text.starts_with("'") || text.starts_with("\"") {
+                return text.chars().nth(1).map(|c| c as u32 as f64).unwrap_or(0.0);
+            }
+            if text.len() == 0 {
+                return 0.0;
+            }
+            match from_str::<f64>(text) {
+                Some(value) => { value }
+                None => {
+                    self.complain(format!("gash: printf: {:s}: invalid number", arg));
+                    self.printed.failed = true;
+                    0.0
+                }
+            }
+        }
+    }
+
+    // Pad text to width, on the left unless the flags have a '-', after
+    // cutting it down to precision characters.
+    fn pad(text: ~str, flags: &str, width: Option<i64>, precision: Option<i64>) -> ~str {
+        let text = match precision {
+            Some(precision) if (precision as uint) < text.char_len() => {
+                text.chars().take(precision as uint).collect()
+            }
+            _ => { text }
+        };
+        let width = width.unwrap_or(0) as uint;
+        let len = text.char_len();
+        if len >= width {
+            return text;
+        }
+        let padding = " ".repeat(width - len);
+        if flags.contains_char('-') { text + padding } else { padding + text }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{printf, quote};
+
+        fn run(format: &str, args: &[&str]) -> ~str {
+            let args: ~[~str] = args.iter().map(|arg| arg.to_owned()).collect();
+            printf(format, args, 0).output
+        }
+
+        // Read back a word quoted by quote(): $'...' takes printf's
+        // escapes, and otherwise each backslash quotes the next character.
+        fn unquote(word: &str) -> ~str {
+            if word.starts_with("$'") {
+                return run(word.slice(2, word.len() - 1), &[]);
+            }
+            if word == "''" {
+                return ~"";
+            }
+            let mut out = ~"";
+            let mut escaped = false;
+            for c in word.chars() {
+                if c == '\\' && !escaped {
+                    escaped = true;
+                    continue;
+                }
+                out.push_char(c);
+                escaped = false;
+            }
+            out
+        }
+
+        #[test]
+        fn format_is_reused_for_leftover_arguments() {
+            assert_eq!(run("%s-", &["a", "b", "c"]), ~"a-b-c-");
+            assert_eq!(run("%s=%d ", &["x", "1", "y"]), ~"x=1 y=0 ");
+            assert_eq!(run("plain", &["a", "b"]), ~"plain");
+        }
+
+        #[test]
+        fn percent_b_stops_at_backslash_c() {
+            assert_eq!(run("%b", &["a\\tb"]), ~"a\tb");
+            assert_eq!(run("%b|%s", &["one\\ctwo", "three"]), ~"one");
+            assert_eq!(run("%s %b", &["x", "y\\c", "z", "w"]), ~"x y");
+        }
+
+        #[test]
+        fn percent_q_round_trips() {
+            let words = &["", "plain", "a b", "it's", "$HOME", "*.rs", "tab\there",
+                         "line\nbreak", "bell\x07", "back\\slash\x01"];
+            for word in words.iter() {
+                let quoted = run("%q", &[*word]);
+                assert_eq!(quoted, quote(*word));
+                assert_eq!(unquote(quoted), word.to_owned());
+            }
+        }
+    }
+}
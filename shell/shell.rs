@@ -2,35 +2,189 @@
 #[ path="helpers.rs"]       mod helpers;
 #[ path="shellprocess.rs"]  mod shellprocess;
 #[ path="parser.rs"]        mod parser;
+#[ path="errors.rs"]        mod errors;
+#[ path="signals.rs"]       mod signals;
+#[ path="terminal.rs"]      mod terminal;
+#[ path="jobs.rs"]          mod jobs;
+#[ path="commands.rs"]      mod commands;
+#[ path="usage.rs"]         mod usage;
+#[ path="limits.rs"]        mod limits;
+#[ path="options.rs"]       mod options;
+#[ path="printf.rs"]        mod printf;
+#[ path="conditions.rs"]    mod conditions;
 
 pub mod shell {
     use std::run::Process;
     use std::os;
     use std::io::{stdin, stdout, stdio};
+    use std::io::stdio::StdReader;
+    use std::io::io_error;
+    use std::io::process::ExitSignal;
+    use std::io::fs::File;
     use std::io::buffered::BufferedReader;
     use std::io::signal::{Listener, Interrupt};
     use std::task::try;
+    use std::hashmap::HashMap;
+    use std::util::replace;
+    use std::vec;
 
-    use helpers::helpers::{split_words, input_redirect, output_redirect, pipe_redirect};
-    use functional::borrowed_maybe;
+    use helpers::helpers::{split_words, expand_vars, expand_words, exit_status, print_err,
+                           strip_comments, self_exe, launcher, exec_program, open_fd, redirect_fd,
+                           duplicate_fd, close_fd, write_fd, decode_bytes, encode_bytes,
+                           has_raw_bytes};
     use shellprocess::fg::FgProcess;
     use shellprocess::bg::BgProcess;
-    use parser::cmd::Cmd;
-    use parser::pathtype::{Read, Write};
+    use shellprocess::wait;
+    use parser::cmd::{Cmd, ListItem};
+    use parser::complete::{is_complete, join_lines};
+    use parser::pathtype::{PathType, Read, Write, Clobber};
+    use errors::errors::{ShellError, NotFound, NotExecutable, RedirectFailed};
+    use signals::signals::{kill, signal_name, signal_number, SIGNALS,
+                           SIGCONT, SIGTSTP, SIGTERM, SIGHUP, SIGINT,
+                           SIGKILL, SIGSTOP, SIGCHLD};
+    use signals::signals;
+    use std::ascii::StrAsciiExt;
+    use terminal::terminal;
+    use terminal::terminal::Termios;
+    use jobs::jobs::{JobTable, Running, Stopped};
+    use commands::commands::{CommandTable, program_names, edit_distance};
+    use usage::usage::{Usage, TimeMode, DEFAULT_FORMAT, report};
+    use extra::time::precise_time_ns;
+    use limits::limits;
+    use std::num::from_str_radix;
+    use limits::limits::{RESOURCES, RLIM_INFINITY, ALL_PERMISSIONS, find_resource};
+    use options::options::{Options, SET_OPTIONS, SHOPT_OPTIONS, option_name};
+    use printf::printf::{printf, echo_escapes};
+    use conditions::conditions::{test, condition};
+    use extra::time::get_time;
     
-    use std::libc::consts::os::posix88::{STDOUT_FILENO, STDIN_FILENO};
+    use std::libc::consts::os::posix88::{STDOUT_FILENO, STDIN_FILENO, STDERR_FILENO,
+                                         O_RDONLY, O_WRONLY, O_CREAT, O_TRUNC, O_APPEND};
+    use std::libc::{c_int, size_t, getpid};
     use std::libc::types::os::arch::posix88::pid_t;
-    use std::libc;
 
-    extern {
-        pub fn kill(pid: pid_t, sig: libc::c_int) -> libc::c_int;
+    // The builtins, for suggesting one when a command can't be found.
+    static BUILTINS: &'static [&'static str] = &[
+        "[", "bg", "cd", "coproc", "disown", "echo", "exec", "exit", "fg", "hash", "history",
+        "jobs", "kill", "printf", "set", "shopt", "test", "trap", "ulimit", "umask", "wait",
+    ];
+
+    // Pseudo-signals `trap` takes besides the real ones, numbered so as not
+    // to clash with them: EXIT (0) when the shell exits, ERR when a command
+    // fails and DEBUG before each command. RETURN is known only to be
+    // refused, since gash has no functions or sourced scripts to return
+    // from.
+    static EXIT_TRAP   : int = 0;
+    static ERR_TRAP    : int = -1;
+    static DEBUG_TRAP  : int = -2;
+    static RETURN_TRAP : int = -3;
+
+    // The number `trap` files a signal spec under, real or pseudo.
+    fn trap_number(spec: &str) -> Option<int> {
+        match spec.to_ascii_upper().as_slice() {
+            "EXIT"   => { Some(EXIT_TRAP) }
+            "ERR"    => { Some(ERR_TRAP) }
+            "DEBUG"  => { Some(DEBUG_TRAP) }
+            "RETURN" => { Some(RETURN_TRAP) }
+            _        => { signal_number(spec).map(|sig| sig as int) }
+        }
+    }
+
+    fn trap_name(number: int) -> ~str {
+        match number {
+            EXIT_TRAP   => { ~"EXIT" }
+            ERR_TRAP    => { ~"ERR" }
+            DEBUG_TRAP  => { ~"DEBUG" }
+            RETURN_TRAP => { ~"RETURN" }
+            _ => {
+                match signal_name(number as i32) {
+                    Some(name) => { format!("SIG{:s}", name) }
+                    None       => { number.to_str() }
+                }
+            }
+        }
+    }
+
+    // Split a redirection word like "2>&1", ">>log" or "3<" into its fd (if
+    // given), operator and target (empty if it's the next word).
+    fn parse_redirection(word: &str) -> Option<(Option<c_int>, ~str, ~str)> {
+        let digits = word.find(|c: char| !c.is_digit()).unwrap_or(word.len());
+        let rest = word.slice_from(digits);
+        let ops = ["&>", ">>", ">&", ">|", "<&", ">", "<"];
+        let op = match ops.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => { *op }
+            None     => { return None; }
+        };
+        if digits > 0 && op == "&>" {
+            return None;
+        }
+        let fd = if digits > 0 { from_str::<c_int>(word.slice_to(digits)) } else { None };
+        Some((fd, op.to_owned(), rest.slice_from(op.len()).to_owned()))
+    }
+
+    // What the shell is doing, as far as Ctrl-C is concerned: reading a
+    // command after showing a prompt, or running one, perhaps in a
+    // foreground process group of its own.
+    enum Activity {
+        Reading(~str),
+        Running(Option<pid_t>),
     }
 
     pub struct Shell {
         cmd_prompt : ~str,
         history    : ~[~str],
-        processes  : ~[~BgProcess],
+        jobs       : JobTable,
+        // Where the programs run so far were found.
+        commands   : CommandTable,
         broken : bool,
+        // Shell variables. Lookups fall back to the environment.
+        vars       : HashMap<~str, ~str>,
+        // Names of running coprocesses, keyed by pid.
+        coprocs    : HashMap<i32, ~str>,
+        // Exit status of the last command line, i.e. $?.
+        last_status : int,
+        // Line of the script the current command starts on.
+        lineno     : uint,
+        // True if the shell owns a terminal and runs jobs in their own
+        // process groups.
+        job_control : bool,
+        // True when commands are being read from the user, who is told
+        // about jobs that finish.
+        interactive : bool,
+        // The options `set` and `shopt` change.
+        options    : Options,
+        // The exit status of the rightmost command of the foreground
+        // pipeline, before its last one, that failed; for pipefail.
+        pipe_failure : int,
+        // Keeps the interrupt handler up to date with what the shell is
+        // doing, and hears from it when Ctrl-C is pressed.
+        activity   : Option<Chan<Activity>>,
+        interrupts : Option<Port<()>>,
+        // Set after warning that `exit` would leave jobs behind.
+        exit_warned : bool,
+        // Commands to run on signals and pseudo-signals, by trap_number.
+        traps      : HashMap<int, ~str>,
+        // The handlers trapped signals had before, to go back to.
+        saved_handlers : HashMap<int, size_t>,
+        // Trapped signals the shell noticed itself (Ctrl-C at the prompt).
+        pending_traps : ~[int],
+        // Set while a trap runs, so that it doesn't set off more.
+        in_trap    : bool,
+        // Set when a command is interrupted, so the rest of the command
+        // line or script is skipped.
+        interrupted : bool,
+        // The terminal modes from just before the foreground job got the
+        // terminal, to restore when it finishes or stops.
+        shell_modes : Option<Termios>,
+        // Process group of the foreground job being started, if any.
+        fg_pgid    : Option<pid_t>,
+        // The command line being run, for the job table.
+        current_line : ~str,
+        // When the shell started, in seconds since the epoch.
+        start_time : i64,
+        // What the processes of the pipeline being timed have used, while
+        // there is one.
+        usage : Option<Usage>,
     }
 
     impl Shell {
@@ -38,24 +192,75 @@ pub mod shell {
             Shell {
                 cmd_prompt: prompt_str.to_owned(),
                 history: ~[],
-                processes: ~[],
+                jobs: JobTable::new(),
+                commands: CommandTable::new(),
                 broken: false,
+                vars: HashMap::new(),
+                coprocs: HashMap::new(),
+                last_status: 0,
+                lineno: 1,
+                job_control: false,
+                interactive: false,
+                options: Options::new(),
+                pipe_failure: 0,
+                activity: None,
+                interrupts: None,
+                exit_warned: false,
+                traps: HashMap::new(),
+                saved_handlers: HashMap::new(),
+                pending_traps: ~[],
+                in_trap: false,
+                interrupted: false,
+                shell_modes: None,
+                fg_pgid: None,
+                current_line: ~"",
+                start_time: get_time().sec,
+                usage: None,
             }
         }
 
         // Start the shell with an interrupt handler. Only needed when
         // an interactive shell is used.
         pub fn start(&mut self) {
+            self.interactive = true;
+            self.job_control = terminal::init();
             // Setup the interrupt handler. Has to happen here, or it won't 
-            // retain control over interrupts.
+            // retain control over interrupts. Ctrl-C at the prompt throws the
+            // line away and starts a fresh one; while a job runs in the
+            // foreground the terminal sends it to the job's group, and one
+            // sent to the shell itself is passed on.
             let mut listener = Listener::new();
             listener.register(Interrupt);
             let port = listener.port;
+            let (activity_port, activity_chan) = Chan::new();
+            let (interrupt_port, interrupt_chan) = Chan::new();
+            self.activity = Some(activity_chan);
+            self.interrupts = Some(interrupt_port);
             spawn(proc() {
                 try(proc() {
+                    let mut activity = Running(None);
                     loop {
                         match port.recv_opt() {
                             Some(Interrupt) => {
+                                loop {
+                                    match activity_port.try_recv() {
+                                        Some(latest) => { activity = latest; }
+                                        None         => { break; }
+                                    }
+                                }
+                                match activity {
+                                    Reading(ref prompt) => {
+                                        print("\n");
+                                        print(*prompt);
+                                        stdio::flush();
+                                    }
+                                    Running(Some(pgid)) => unsafe {
+                                        kill(-pgid, SIGINT);
+                                    },
+                                    Running(None) => {
+                                    }
+                                }
+                                interrupt_chan.try_send(());
                             }
                             None => {
                                 break;
@@ -76,208 +281,2502 @@ pub mod shell {
         fn display_prompt(&mut self) {
             // Standard input reader
             let mut stdin = BufferedReader::new(stdin());
+            self.disown_dead();
+            if !self.run_traps() {
+                return;
+            }
             // Show the prompt
             print(self.cmd_prompt);
             stdio::flush();
 
-            let line = stdin.read_line().unwrap();
+            let watcher = if self.options.notify { self.jobs.watch() } else { None };
+            self.tell(Reading(self.cmd_prompt.clone()));
+            let line = self.read_command(&mut stdin);
+            self.tell(Running(None));
+            match watcher {
+                Some(watcher) => { self.jobs.unwatch(watcher); }
+                None          => { }
+            }
+            let line = match line {
+                Some(line) => { line }
+                None => {
+                    // End of input: leave as if "exit" had been typed. At a
+                    // terminal the user can still type, so jobs get the same
+                    // warning first.
+                    println("");
+                    let warned = replace(&mut self.exit_warned, false);
+                    if self.job_control && !warned && self.warn_jobs() {
+                        self.display_prompt();
+                        return;
+                    }
+                    self.hang_up_jobs();
+                    return;
+                }
+            };
             let cmd_line = line.trim().to_owned();
-            let program = cmd_line.splitn(' ', 1).nth(0).expect("no program");
-            self.disown_dead();
 
-            match program {
-                "" =>  {
-                    self.display_prompt(); 
+            match cmd_line.splitn(' ', 1).nth(0).expect("no program") {
+                "" | "exit" | "history" => { }
+                _ => {
+                    self.push_hist(cmd_line);
+                }
+            }
+            if self.run_line(cmd_line) {
+                self.display_prompt();
+            }
+        }
+
+        // Run one line of input. Returns false if the shell should exit.
+        pub fn run_line(&mut self, cmd_line: &str) -> bool {
+            if self.options.verbose && !self.in_trap {
+                print_err(cmd_line.trim_right());
+            }
+            let cmd_line = strip_comments(cmd_line);
+            self.run_cmdline(cmd_line.trim())
+        }
+
+        // Run one pipeline of a command line in the foreground, which may be
+        // a builtin. Returns false if the shell should exit.
+        fn run_foreground(&mut self, text: &str, cmd: ~Cmd) -> bool {
+            // The warning about jobs only holds off an exit that comes
+            // straight after it.
+            let warned = replace(&mut self.exit_warned, false);
+            if !cmd.is_simple() {
+                self.run_pipeline(text, cmd);
+                return true;
+            }
+            match cmd.condition {
+                Some(ref inner) => {
+                    self.conditional(*inner);
+                    return true;
                 }
+                None => { }
+            }
+            match cmd.program.as_slice() {
                 "exit" =>  { 
-                    self.kill_all();
+                    return !self.exit(text, warned);
+                }
+                "disown" => {
+                    self.disown(text);
                 }
                 "history" => {
                     self.show_hist();
-                    self.display_prompt();
                 }
                 "jobs" => {
-                    self.push_hist(cmd_line);
-                    self.jobs();
-                    self.display_prompt();
+                    self.list_jobs(text);
+                }
+                "fg" => {
+                    self.fg(text);
+                }
+                "bg" => {
+                    self.bg(text);
+                }
+                "kill" => {
+                    self.kill_builtin(text);
+                }
+                "wait" => {
+                    self.wait_builtin(text);
                 }
                 "cd" =>  {
-                    self.push_hist(cmd_line);
-                    self.chdir(cmd_line); 
-                    self.display_prompt();
+                    self.chdir(text); 
+                }
+                "set" => {
+                    self.set_builtin(text);
+                }
+                "shopt" => {
+                    self.shopt(text);
+                }
+                "echo" | "printf" => {
+                    let output = self.output_builtin(split_words(text));
+                    stdio::flush();
+                    write_fd(STDOUT_FILENO, output.unwrap(), false);
+                }
+                "test" | "[" => {
+                    self.test_builtin(text);
+                }
+                "hash" => {
+                    self.hash(text);
+                }
+                "ulimit" => {
+                    self.ulimit(text);
+                }
+                "umask" => {
+                    self.umask(text);
+                }
+                "coproc" => {
+                    self.coproc(text);
+                }
+                "exec" => {
+                    return self.exec(text);
+                }
+                "trap" => {
+                    self.trap_builtin(text);
                 }
                 _ => { 
-                    self.push_hist(cmd_line);
-                    self.run_cmdline(cmd_line);
-                    self.display_prompt();
+                    self.run_pipeline(text, cmd);
                 }
             }
+            true
         }
 
-        fn run(&mut self, elem : ~Cmd) -> Option<~Process> {
-            if elem.pipe.is_some() {
-                let left = self.parse_process(elem.clone(), None, None).expect("Couldn't spawn!");
-                Some(elem.iter().fold(left, |left, right| {
-                    let right = self.pipe_file(right).expect("Couldn't spawn!");
-                    pipe_redirect(left,right)
-                }))
+        // Run a script file. Lines are gathered into complete commands the
+        // same way interactive input is, and a #! first line is skipped.
+        pub fn run_script(&mut self, path: &Path) {
+            let mut error = None;
+            let contents = io_error::cond.trap(|e| { error = Some(e); }).inside(|| {
+                File::open(path).map(|mut file| file.read_to_end())
+            });
+            let contents = match contents {
+                Some(bytes) => { decode_bytes(bytes) }
+                None => {
+                    let reason = error.map_default(~"cannot read file", |e| e.desc.to_owned());
+                    print_err(format!("gash: {}: {:s}", path.display(), reason));
+                    self.last_status = 127;
+                    return;
+                }
+            };
+            let mut command = ~"";
+            for (i, line) in contents.lines().enumerate() {
+                if i == 0 && line.starts_with("#!") {
+                    continue;
+                }
+                if command.len() == 0 {
+                    self.lineno = i + 1;
+                }
+                command.push_str(line);
+                command.push_char('\n');
+                if is_complete(command) {
+                    let line = join_lines(command);
+                    command = ~"";
+                    if !self.run_line(line) || !self.run_traps() || self.interrupted {
+                        return;
+                    }
+                }
             }
-            else {
-                self.pipe_file(elem)
+            if command.trim().len() > 0 {
+                print_err(format!("gash: {}: line {:u}: syntax error: unexpected end of file",
+                                  path.display(), self.lineno));
+                self.last_status = 2;
             }
         }
 
-        // "Pipe" output to or from a file. If no file is available, just return
-        // the created process.
-        fn pipe_file(&mut self, elem : ~Cmd) -> Option<~Process> {
-            match elem.clone().file {
-                Some(file) => {
-                    match file.mode {
-                        Read => {
-                            let process = self.to_process(elem).expect("Couldn't spawn!");
-                            Some(input_redirect(process, &file.path))
+        // Read a command from stdin. While the input is incomplete (an open
+        // quote, a trailing pipe, an unfinished `if`...) keep reading lines,
+        // prompting with $PS2. Returns None at the end of input.
+        fn read_command(&mut self, stdin: &mut BufferedReader<StdReader>) -> Option<~str> {
+            let mut input = match self.read_input_line(stdin) {
+                Some(line) => { line }
+                None       => { return None; }
+            };
+            self.check_interrupt();
+            while !is_complete(input) {
+                let prompt = self.lookup("PS2").unwrap_or(~"> ");
+                print(prompt);
+                stdio::flush();
+                match self.read_input_line(stdin) {
+                    Some(line) => {
+                        if self.check_interrupt() {
+                            // Ctrl-C threw away what came before; this is
+                            // the start of a new command.
+                            input = line;
                         }
-                        Write => {
-                            let process = self.parse_process(elem, None, None).expect("Couldn't spawn!");
-                            output_redirect(process, &file.path);
-                            None
+                        else {
+                            input.push_str(line);
                         }
                     }
+                    None => {
+                        print_err("gash: syntax error: unexpected end of file");
+                        self.last_status = 2;
+                        return Some(~"");
+                    }
+                }
+            }
+            // Backslash-newline is a line continuation, not part of the
+            // command.
+            Some(join_lines(input))
+        }
+
+        // Note any Ctrl-C the interrupt handler has seen since the last
+        // look, setting $? to 130 as if a command had been killed by it.
+        // Returns true if there was one.
+        fn check_interrupt(&mut self) -> bool {
+            let mut seen = false;
+            match self.interrupts {
+                Some(ref port) => {
+                    while port.try_recv().is_some() {
+                        seen = true;
+                    }
                 }
                 None => {
-                    self.to_process(elem)
                 }
             }
+            if seen {
+                self.last_status = 130;
+                if self.traps.contains_key(&(SIGINT as int)) {
+                    self.pending_traps.push(SIGINT as int);
+                }
+            }
+            seen
+        }
+
+        // Tell the interrupt handler what the shell is doing.
+        fn tell(&self, activity: Activity) {
+            match self.activity {
+                Some(ref chan) => { chan.try_send(activity); }
+                None           => { }
+            }
         }
 
-        // Make a process from a Cmd. Sets the output to stdout if the "last"
-        // field is true.
-        fn to_process(&mut self, elem : ~Cmd) -> Option<~Process> {
-                self.parse_process(elem.clone(),
-                                None, 
-                                if elem.last { Some(STDOUT_FILENO) }
-                                else { None })
+        // Read one line from stdin. BufferedReader::read_line fails the task
+        // on bytes that aren't UTF-8, so read raw bytes and reject such lines
+        // here instead. Commands are run with std::run, which only takes ~str
+        // arguments, so gash can't hand other bytes on intact; the error
+        // says so rather than mangling them.
+        fn read_input_line(&mut self, stdin: &mut BufferedReader<StdReader>) -> Option<~str> {
+            let bytes = match stdin.read_until('\n' as u8) {
+                Some(bytes) => { bytes }
+                None        => { return None; }
+            };
+            Some(decode_bytes(bytes))
         }
 
-        // Determine the type of the current block, and send it to the right
-        // parsing function.
-        pub fn run_cmdline(&mut self, cmd_line: &str) {
-            let cmd = Cmd::new(cmd_line);
-            if cmd.pipe.is_none() && cmd.file.is_none() {
-                self.parse_process(cmd, Some(STDIN_FILENO), Some(STDOUT_FILENO));
+        // Run a command line: a list of pipelines separated by ';' or '&'.
+        // Sets last_status to the exit status of the last one to run in the
+        // foreground. Returns false if the shell should exit.
+        pub fn run_cmdline(&mut self, cmd_line: &str) -> bool {
+            if cmd_line.trim() == "" {
+                return true;
             }
-            else {
-                self.run(cmd);
+            let items = match Cmd::new(cmd_line, self.lineno) {
+                Ok(items) => { items }
+                Err(e)    => {
+                    e.report(cmd_line);
+                    self.last_status = 2;
+                    return true;
+                }
+            };
+            // set -n only checks a script's syntax.
+            if self.options.noexec && !self.interactive {
+                return true;
+            }
+            self.interrupted = false;
+            // Whether each item is on the left of && or ||, where failing
+            // neither sets off the ERR trap nor exits under set -e.
+            let guarded: ~[bool] = range(0, items.len())
+                .map(|i| i + 1 < items.len() && items[i + 1].run_if.is_some())
+                .collect();
+            for (i, item) in items.move_iter().enumerate() {
+                if self.interrupted {
+                    break;
+                }
+                let ListItem { text: typed, cmd, background, time, raw, run_if } = item;
+                match run_if {
+                    Some(success) if success != (self.last_status == 0) => { continue; }
+                    _ => { }
+                }
+                if !self.run_trap(DEBUG_TRAP) {
+                    return false;
+                }
+                // Each pipeline is expanded just before it runs, so that it
+                // sees what the ones before it did. Builtins that take their
+                // text as typed expand it themselves. An unset variable under
+                // set -u ends a script.
+                let (text, cmd) = if raw {
+                    (typed.clone(), cmd)
+                }
+                else {
+                    let text = match self.expand(typed) {
+                        Some(text) => { text }
+                        None       => { return self.interactive; }
+                    };
+                    if text.trim() == "" {
+                        self.last_status = 0;
+                        continue;
+                    }
+                    match Cmd::pipeline(text, self.lineno) {
+                        Ok(cmd) => { (text, cmd) }
+                        Err(e)  => {
+                            e.report(text);
+                            self.last_status = 2;
+                            continue;
+                        }
+                    }
+                };
+                if self.options.xtrace {
+                    self.trace(text);
+                }
+                let keep_going = match (background, time) {
+                    (true, _) => {
+                        self.run_background(typed, cmd);
+                        true
+                    }
+                    (false, Some(mode)) => { self.run_timed(text, cmd, mode) }
+                    (false, None)       => { self.run_foreground(text, cmd) }
+                };
+                if !keep_going {
+                    return false;
+                }
+                if !background && !guarded[i] && self.last_status != 0 {
+                    if !self.run_trap(ERR_TRAP) || self.options.errexit {
+                        return false;
+                    }
+                }
+                if !self.run_traps() {
+                    return false;
+                }
             }
+            true
         }
 
-        // Parse a new lone process. Background/foreground it appropriately.
-        fn parse_process(&mut self,
-                        cmd: ~Cmd,
-                        stdin: Option<i32>,
-                        stdout:Option<i32>) 
-                        -> Option<~Process> {
-            if (cmd.argv.len() > 0 && cmd.argv.last() == &~"&") {
-                let mut argv = cmd.argv.to_owned();
-                argv.pop();
-                self.make_bg_process(cmd.program.to_owned(), cmd.argv);
+        // Show a command about to run on stderr, for set -x, after the
+        // expansion of $PS4.
+        fn trace(&mut self, text: &str) {
+            let prefix = self.lookup("PS4").unwrap_or(~"+ ");
+            let prefix = expand_vars(prefix, |name| self.lookup(name));
+            print_err(prefix + text);
+        }
+
+        // Run a pipeline preceded by `time`, then report on stderr how long
+        // it took and what its processes used. An empty $TIMEFORMAT turns
+        // the report off.
+        fn run_timed(&mut self, text: &str, cmd: ~Cmd, mode: TimeMode) -> bool {
+            self.usage = Some(Usage::new());
+            let start = precise_time_ns();
+            let keep_going = self.run_foreground(text, cmd);
+            let real = ((precise_time_ns() - start) / 1000) as i64;
+            let usage = self.usage.take().unwrap();
+            let format = self.lookup("TIMEFORMAT").unwrap_or(DEFAULT_FORMAT.to_owned());
+            let times = report(mode, format, text, real, &usage);
+            if times.len() > 0 {
+                print_err(times);
+            }
+            keep_going
+        }
+
+        // Start the elements of a pipeline, each reading from the one
+        // before through a pipe, and wait for them all. Sets last_status to
+        // the exit status of the last one.
+        fn run_pipeline(&mut self, text: &str, cmd: ~Cmd) {
+            self.last_status = 0;
+            self.pipe_failure = 0;
+            self.fg_pgid = None;
+            self.current_line = text.to_owned();
+            self.save_modes();
+            let mut elems = ~[cmd.clone()];
+            elems.extend(&mut cmd.iter());
+            let count = elems.len();
+            // Each element's exit status, by its place in the pipeline.
+            let mut statuses = vec::from_elem(count, 0);
+            let mut processes = ~[];
+            // The read end of the pipe from the element before.
+            let mut input = STDIN_FILENO;
+            for (i, elem) in elems.move_iter().enumerate() {
+                let pipe = if i + 1 < count { Some(os::pipe()) } else { None };
+                let output = match pipe {
+                    Some(ref pipe) => { pipe.out }
+                    None           => { STDOUT_FILENO }
+                };
+                match self.start_element(elem, input, output) {
+                    Ok(Some(process)) => {
+                        processes.push((i, process));
+                    }
+                    Ok(None) => {
+                        statuses[i] = self.last_status;
+                    }
+                    Err(e) => {
+                        self.fail(e);
+                        statuses[i] = self.last_status;
+                    }
+                }
+                // The children have their own copies of the pipe ends now.
+                if input != STDIN_FILENO {
+                    close_fd(input);
+                }
+                match pipe {
+                    Some(pipe) => {
+                        close_fd(pipe.out);
+                        input = pipe.input;
+                    }
+                    None => {
+                    }
+                }
+            }
+            let finished = self.wait_fg(processes, statuses.as_mut_slice(), text);
+            if finished {
+                self.last_status = statuses[count - 1];
+                for &status in statuses.iter() {
+                    if status != 0 {
+                        self.pipe_failure = status;
+                    }
+                }
+                if self.options.pipefail && self.last_status == 0 {
+                    self.last_status = self.pipe_failure;
+                }
+            }
+            self.end_fg_job();
+        }
+
+        // Start one element of a pipeline reading from stdin and writing to
+        // stdout, unless it redirects one of them to a file. echo and printf
+        // are run by the shell itself, and there is no process to wait for.
+        fn start_element(&mut self, elem: ~Cmd, stdin: c_int, stdout: c_int)
+                -> Result<Option<~Process>, ShellError> {
+            let (stdin, stdout, file) = match elem.file {
+                Some(ref file) => {
+                    let fd = match self.open_redirect(file) {
+                        Ok(fd) => { fd }
+                        Err(e) => { return Err(e); }
+                    };
+                    match file.mode {
+                        Read            => { (fd, stdout, Some(fd)) }
+                        Write | Clobber => { (stdin, fd, Some(fd)) }
+                    }
+                }
+                None => {
+                    (stdin, stdout, None)
+                }
+            };
+            let mut argv = ~[elem.program.clone()];
+            argv.push_all(elem.argv.as_slice());
+            let builtin = if elem.group.is_none() && elem.condition.is_none() {
+                self.output_builtin(argv)
+            }
+            else {
                 None
+            };
+            let result = match builtin {
+                Some(output) => {
+                    // Only a pipe to the next element can fill up before
+                    // its reader has started.
+                    write_fd(stdout, output, stdout != STDOUT_FILENO && file != Some(stdout));
+                    Ok(None)
+                }
+                None => {
+                    self.parse_process(elem, Some(stdin), Some(stdout)).map(|process| Some(process))
+                }
+            };
+            match file {
+                Some(fd) => { close_fd(fd); }
+                None     => { }
+            }
+            result
+        }
+
+        // Run echo or printf, given its words, and return what it printed.
+        // The output goes wherever the command's stdout does, which is up
+        // to the caller. None for any other command.
+        fn output_builtin(&mut self, argv: ~[~str]) -> Option<~str> {
+            match argv[0].as_slice() {
+                "echo"   => { Some(self.echo(argv)) }
+                "printf" => { Some(self.printf_builtin(argv)) }
+                _        => { None }
+            }
+        }
+
+        // Open the file a redirection names, for a process to read from or
+        // write to.
+        fn open_redirect(&self, file: &PathType) -> Result<c_int, ShellError> {
+            let flags = match file.mode {
+                Read => {
+                    O_RDONLY
+                }
+                Write => {
+                    match self.check_clobber(&file.path) {
+                        Ok(()) => { }
+                        Err(e) => { return Err(e); }
+                    }
+                    O_WRONLY | O_CREAT | O_TRUNC
+                }
+                Clobber => {
+                    O_WRONLY | O_CREAT | O_TRUNC
+                }
+            };
+            open_fd(format!("{}", file.path.display()), flags)
+        }
+
+        // Start a pipeline ended by '&' as a background job. A lone command
+        // runs directly; anything more (pipes, redirections, a group, an
+        // and-or list) runs in a subshell, so the whole pipeline is one job.
+        // The subshell is given the text as typed, since it expands it
+        // itself.
+        fn run_background(&mut self, typed: &str, cmd: ~Cmd) {
+            self.last_status = 0;
+            self.current_line = typed + " &";
+            if cmd.is_simple() {
+                self.make_bg_process(cmd.program.to_owned(), cmd.argv.to_owned(), None);
             }
             else {
-                Some(~(FgProcess::new(cmd.program.to_owned(), cmd.argv, stdin, stdout).run()))
+                let env = self.subshell_env();
+                self.make_bg_process(self_exe(), ~[~"-c", typed.to_owned()], Some(env));
             }
         }
 
-        // background processes.
-        fn make_bg_process(&mut self, cmd: ~str, argv: ~[~str]) {
-            let mut process = BgProcess::new(cmd.to_owned(), argv);
-            match process.run() {
-                Some(pid) => {
-                    println!("{:s} {:i}", cmd, pid);
-                    self.processes.push(~process);
+        // The environment a subshell starts with: the shell's own, and its
+        // variables, which a subshell sees as well.
+        fn subshell_env(&self) -> ~[(~str, ~str)] {
+            let mut env: ~[(~str, ~str)] = os::env().move_iter()
+                .filter(|&(ref name, _)| !self.vars.contains_key(name))
+                .collect();
+            for (name, value) in self.vars.iter() {
+                env.push((name.clone(), value.clone()));
+            }
+            env
+        }
+
+        // Wait for the processes of the foreground job, noting each one's
+        // exit status in statuses by its place in the pipeline. Under job
+        // control the whole process group is watched, so that a stop of
+        // any of them (Ctrl-Z in `cat | vi`) is seen: the ones still
+        // running then become a stopped job, and false is returned.
+        fn wait_fg(&mut self, mut processes: ~[(uint, ~Process)], statuses: &mut [int],
+                   cmd_line: &str) -> bool {
+            while processes.len() > 0 {
+                let next = match self.fg_pgid {
+                    Some(pgid) if self.job_control => {
+                        match wait::wait_for_group(pgid) {
+                            Some((_, wait::Stopped)) => {
+                                let rest = processes.move_iter()
+                                    .map(|(_, process)| process).collect();
+                                let mut job = BgProcess::from_processes(cmd_line.to_owned(), rest,
+                                                                        pgid, self.jobs.exit_chan());
+                                job.modes = terminal::get_modes();
+                                let id = self.jobs.add(cmd_line.to_owned(), ~job, Stopped);
+                                self.report_stopped(id);
+                                return false;
+                            }
+                            Some((pid, wait::Exited)) => {
+                                processes.iter().position(|&(_, ref process)| process.get_id() == pid)
+                                    .unwrap_or(0)
+                            }
+                            None => {
+                                0
+                            }
+                        }
+                    }
+                    _ => {
+                        0
+                    }
+                };
+                let (element, mut process) = processes.remove(next);
+                if self.usage.is_some() {
+                    match Usage::of(process.get_id()) {
+                        Some(used) => { self.usage.get_mut_ref().add(&used); }
+                        None       => { }
+                    }
+                }
+                let exit = process.finish();
+                statuses[element] = exit_status(exit);
+                match exit {
+                    // A script with an INT trap keeps going after running it.
+                    ExitSignal(sig) if sig == SIGINT as int && element + 1 == statuses.len() => {
+                        if !self.traps.contains_key(&(SIGINT as int)) {
+                            self.interrupted = true;
+                        }
+                    }
+                    _ => { }
+                }
+            }
+            true
+        }
+
+        fn report_stopped(&mut self, id: uint) {
+            println!("\n{:s}", self.jobs.describe(id, false));
+            self.last_status = 128 + SIGTSTP as int;
+        }
+
+        // Job builtins other than `jobs` need job control.
+        fn check_job_control(&mut self, builtin: &str) -> bool {
+            if !self.job_control {
+                print_err(format!("gash: {:s}: no job control", builtin));
+                self.last_status = 1;
+            }
+            self.job_control
+        }
+
+        // Find the job named by a job spec (a bare number n is taken as %n),
+        // or the current job if there is no spec. Reports the problem and
+        // returns None if there is no such job.
+        fn find_job(&mut self, spec: Option<&str>, builtin: &str) -> Option<uint> {
+            let found = match spec {
+                Some(spec) => {
+                    if spec.starts_with("%") {
+                        self.jobs.find(spec)
+                    }
+                    else {
+                        self.jobs.find("%" + spec)
+                    }
                 }
                 None => {
+                    match self.jobs.current() {
+                        Some(id) => { Ok(id) }
+                        None     => { Err(~"current: no such job") }
+                    }
+                }
+            };
+            match found {
+                Ok(id) => {
+                    Some(id)
+                }
+                Err(e) => {
+                    print_err(format!("gash: {:s}: {:s}", builtin, e));
+                    self.last_status = 1;
+                    None
+                }
+            }
+        }
+
+        // Bring a job to the foreground, continuing it if it was stopped,
+        // and wait for it.
+        fn fg(&mut self, cmd_line: &str) {
+            if !self.check_job_control("fg") {
+                return;
+            }
+            let argv = split_words(cmd_line);
+            let id = match self.find_job(argv.iter().nth(1).map(|s| s.as_slice()), "fg") {
+                Some(id) => { id }
+                None     => { return; }
+            };
+            let (pid, pgid, modes, job_line) = {
+                let job = self.jobs.get_mut(id).unwrap();
+                job.state = Running;
+                (job.pid().unwrap(), job.group().unwrap(),
+                 job.process.modes.clone(), job.cmd_line.clone())
+            };
+            self.jobs.touch(id);
+            println!("{:s}", job_line);
+            self.save_modes();
+            terminal::give_to(pgid);
+            match modes {
+                Some(ref modes) => { terminal::set_modes(modes); }
+                None => { }
+            }
+            self.fg_pgid = Some(pgid);
+            unsafe {
+                kill(-pgid, SIGCONT);
+            }
+            match wait::wait_for_job(pgid, pid) {
+                wait::Stopped => {
+                    {
+                        let job = self.jobs.get_mut(id).unwrap();
+                        job.state = Stopped;
+                        job.process.modes = terminal::get_modes();
+                    }
+                    self.jobs.touch(id);
+                    self.report_stopped(id);
+                }
+                wait::Exited => {
+                    self.jobs.wait_for(id);
+                    self.last_status = self.job_status(id).unwrap();
+                    self.consume_job(id);
+                }
+            }
+            self.end_fg_job();
+        }
+
+        // Continue a stopped job in the background.
+        fn bg(&mut self, cmd_line: &str) {
+            if !self.check_job_control("bg") {
+                return;
+            }
+            let argv = split_words(cmd_line);
+            let id = match self.find_job(argv.iter().nth(1).map(|s| s.as_slice()), "bg") {
+                Some(id) => { id }
+                None     => { return; }
+            };
+            {
+                let job = self.jobs.get_mut(id).unwrap();
+                if job.state != Stopped {
+                    print_err(format!("gash: bg: job {:u} already in background", id));
+                    self.last_status = 1;
+                    return;
+                }
+                unsafe {
+                    kill(-job.group().unwrap(), SIGCONT);
                 }
+                job.state = Running;
+                println!("[{:u}]+ {:s} &", id, job.cmd_line);
             }
+            self.jobs.touch(id);
+            self.last_status = 0;
         }
 
-        // Nice extra feature: list running jobs.
-        fn jobs(&mut self) {
-            for cmd in self.processes.iter() {
-                print!("{:s}", cmd.command);
-                match cmd.pid {
-                    Some(pid) => {
-                        println!(" {:i}", pid);
+        // kill [-s SIG | -SIG | -n N] pid|%job...
+        // kill -l [N|SIG...]
+        // Signals given as job specs go to the job's whole process group.
+        fn kill_builtin(&mut self, cmd_line: &str) {
+            let usage = "kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]";
+            let argv = split_words(cmd_line);
+            let mut args: &[~str] = argv.slice_from(1);
+            if args.len() == 0 {
+                print_err(usage);
+                self.last_status = 2;
+                return;
+            }
+            if args[0] == ~"-l" || args[0] == ~"-L" {
+                self.list_signals(args.slice_from(1));
+                return;
+            }
+            let mut sig = SIGTERM;
+            let first = args[0].clone();
+            if first == ~"-s" || first == ~"-n" {
+                if args.len() < 2 {
+                    print_err(usage);
+                    self.last_status = 2;
+                    return;
+                }
+                sig = match signal_number(args[1]) {
+                    Some(sig) => { sig }
+                    None => {
+                        print_err(format!("gash: kill: {:s}: invalid signal specification", args[1]));
+                        self.last_status = 1;
+                        return;
                     }
+                };
+                args = args.slice_from(2);
+            }
+            else if first == ~"--" {
+                args = args.slice_from(1);
+            }
+            else if first.starts_with("-") && first.len() > 1 {
+                sig = match signal_number(first.slice_from(1)) {
+                    Some(sig) => { sig }
                     None => {
-                        println("");
+                        print_err(format!("gash: kill: {:s}: invalid signal specification",
+                                          first.slice_from(1)));
+                        self.last_status = 1;
+                        return;
                     }
+                };
+                args = args.slice_from(1);
+            }
+            if args.len() == 0 {
+                print_err(usage);
+                self.last_status = 2;
+                return;
+            }
+            let mut status = 0;
+            for target in args.iter() {
+                if !self.send_signal(*target, sig) {
+                    status = 1;
                 }
             }
+            self.last_status = status;
         }
 
-        // Remove dead processes from the list of processes.
-        fn disown_dead(&mut self) {
-            let mut dead : ~[uint];
-            dead = ~[];
-            let mut i = 0;
-            for cmd in self.processes.iter() {
-                borrowed_maybe(&cmd.exit_port, |port| match port.try_recv() {
-                    Some(_) => {dead.push(i); Some(i)},
-                    _ => None,
-                });
-                i += 1;
+        // Send sig to a pid or a job spec. Returns false on failure.
+        fn send_signal(&mut self, target: &str, sig: i32) -> bool {
+            let (pid, job) = if target.starts_with("%") {
+                match self.jobs.find(target) {
+                    Ok(id) => {
+                        let job = self.jobs.get(id).unwrap();
+                        let pid = if self.job_control {
+                            -job.group().unwrap()
+                        }
+                        else {
+                            job.pid().unwrap()
+                        };
+                        (pid, Some(id))
+                    }
+                    Err(e) => {
+                        print_err(format!("gash: kill: {:s}", e));
+                        return false;
+                    }
+                }
+            }
+            else {
+                match from_str::<pid_t>(target) {
+                    Some(pid) => { (pid, None) }
+                    None => {
+                        print_err(format!("gash: kill: {:s}: arguments must be process or job IDs",
+                                          target));
+                        return false;
+                    }
+                }
+            };
+            if unsafe { kill(pid, sig) } != 0 {
+                print_err(format!("gash: kill: ({:d}) - {:s}", pid, os::last_os_error()));
+                return false;
             }
-            for &p in dead.iter() {
-                self.processes.remove(p);
+            match job {
+                Some(id) => {
+                    let job = self.jobs.get_mut(id).unwrap();
+                    if job.state == Stopped {
+                        // A stopped job can't act on TERM or HUP until it is
+                        // continued.
+                        if sig == SIGTERM || sig == SIGHUP {
+                            unsafe {
+                                kill(pid, SIGCONT);
+                            }
+                        }
+                        if sig == SIGCONT || sig == SIGTERM || sig == SIGHUP {
+                            job.state = Running;
+                        }
+                    }
+                }
+                None => {
+                }
             }
+            true
         }
 
-        // Kill all the background jobs. Used when "exit" is called at the CLI.
-        fn kill_all(&mut self) {
-            for p in self.processes.iter() {
-                match p.pid {
-                    Some(pid) => {
-                        unsafe { 
-                            // Kill perhaps isn't the best way to do this, but
-                            // without it we have no way of killing our background
-                            // processes with only the PID - we'd need the process
-                            // object itself, and that can't be moved out of the
-                            // spawn() that it's trapped inside.
-                            kill(pid, 15); 
+        // kill -l: list every signal, or translate the given signal numbers
+        // to names and names to numbers. A number above 128 is taken to be
+        // an exit status, as in `kill -l $?`.
+        fn list_signals(&mut self, args: &[~str]) {
+            self.last_status = 0;
+            if args.len() == 0 {
+                let mut row = ~[];
+                for &(sig, name) in SIGNALS.iter() {
+                    row.push(format!("{:2d}) SIG{:s}", sig, name));
+                    if row.len() == 5 {
+                        println(row.connect("\t"));
+                        row = ~[];
+                    }
+                }
+                if row.len() > 0 {
+                    println(row.connect("\t"));
+                }
+                return;
+            }
+            for arg in args.iter() {
+                match from_str::<i32>(*arg) {
+                    Some(n) => {
+                        let sig = if n > 128 { n - 128 } else { n };
+                        match signal_name(sig) {
+                            Some(name) => { println(name); }
+                            None => {
+                                print_err(format!("gash: kill: {:s}: invalid signal specification", *arg));
+                                self.last_status = 1;
+                            }
                         }
                     }
                     None => {
+                        match signal_number(*arg) {
+                            Some(sig) => { println!("{:d}", sig); }
+                            None => {
+                                print_err(format!("gash: kill: {:s}: invalid signal specification", *arg));
+                                self.last_status = 1;
+                            }
+                        }
                     }
                 }
             }
         }
 
-        // Push a new command onto history.
-        fn push_hist(&mut self, cmd_line: &str) {
-            &self.history.push(cmd_line.to_owned());
+        // wait [-n] [pid|%job...]
+        // With no arguments, wait for every job and return 0. Otherwise wait
+        // for the given jobs and return the status of the last one, or with
+        // -n, for whichever finishes first and return its status.
+        fn wait_builtin(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let mut first_only = false;
+            let mut ids = ~[];
+            // The status of the last argument, if it named a process that
+            // had already finished.
+            let mut reaped = None;
+            for arg in argv.slice_from(1).iter() {
+                if *arg == ~"-n" {
+                    first_only = true;
+                }
+                else if arg.starts_with("%") {
+                    match self.jobs.find(*arg) {
+                        Ok(id) => { ids.push(id); }
+                        Err(e) => {
+                            print_err(format!("gash: wait: {:s}", e));
+                            self.last_status = 127;
+                            return;
+                        }
+                    }
+                }
+                else {
+                    let pid = match from_str::<pid_t>(*arg) {
+                        Some(pid) => { pid }
+                        None => {
+                            print_err(format!("gash: wait: `{:s}': not a pid or valid job spec", *arg));
+                            self.last_status = 2;
+                            return;
+                        }
+                    };
+                    self.jobs.reap();
+                    let found = self.jobs.iter().find(|job| job.pid() == Some(pid)).map(|job| job.id);
+                    match found {
+                        Some(id) => { ids.push(id); }
+                        None => {
+                            // It may have finished and been reported already.
+                            match self.jobs.take_status(pid) {
+                                Some(status) if first_only => {
+                                    self.last_status = status;
+                                    return;
+                                }
+                                Some(status) => {
+                                    reaped = Some(status);
+                                    continue;
+                                }
+                                None => {
+                                    print_err(format!("gash: wait: pid {:d} is not a child of this shell", pid));
+                                    self.last_status = 127;
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+                reaped = None;
+            }
+            let all = ids.len() == 0 && reaped.is_none();
+            if all {
+                ids = self.jobs.iter().map(|job| job.id).collect();
+            }
+            if ids.len() == 0 {
+                self.last_status = match reaped {
+                    Some(status) => { status }
+                    None         => { if first_only { 127 } else { 0 } }
+                };
+                return;
+            }
+            loop {
+                self.jobs.reap();
+                // A job that is stopped won't finish until it is continued,
+                // so wait gives up on it.
+                let stopped = ids.iter()
+                    .find(|&&id| self.jobs.get(id).map_default(false, |job| job.state == Stopped))
+                    .map(|&id| id);
+                match stopped {
+                    Some(id) => {
+                        self.report_stopped(id);
+                        return;
+                    }
+                    None => {
+                    }
+                }
+                let finished: ~[uint] = ids.iter()
+                    .filter(|&&id| self.job_status(id).is_some())
+                    .map(|&id| id)
+                    .collect();
+                // -n takes jobs in the order they finished.
+                match self.jobs.first_finished(ids) {
+                    Some(id) if first_only => {
+                        self.last_status = self.job_status(id).unwrap();
+                        self.consume_job(id);
+                        return;
+                    }
+                    _ => {
+                    }
+                }
+                if !first_only && finished.len() == ids.len() {
+                    let last = *ids.last();
+                    self.last_status = match reaped {
+                        _ if all     => { 0 }
+                        Some(status) => { status }
+                        None         => { self.job_status(last).unwrap() }
+                    };
+                    for &id in ids.iter() {
+                        self.consume_job(id);
+                    }
+                    return;
+                }
+                self.jobs.wait_next();
+            }
         }
 
-        // Show the history.
-        fn show_hist(&mut self) {
-            let mut hist = ~"";
-            for i in self.history.iter() {
-                let s = i.to_owned();
-                match hist {
-                    ~"" => { hist = s; }
-                    _   => { hist = hist + "\n" + s; }
+        // The exit status of a job, if it has finished.
+        fn job_status(&self, id: uint) -> Option<int> {
+            self.jobs.get(id).and_then(|job| job.state.status())
+        }
+
+        // Put a newly spawned foreground process into the foreground job's
+        // process group, making the group (and handing it the terminal) if
+        // this is the job's first process.
+        fn join_fg_job(&mut self, pid: pid_t) {
+            if !self.job_control {
+                return;
+            }
+            match self.fg_pgid {
+                Some(pgid) => {
+                    terminal::set_pgid(pid, pgid);
+                }
+                None => {
+                    terminal::set_pgid(pid, pid);
+                    terminal::give_to(pid);
+                    self.fg_pgid = Some(pid);
+                    self.tell(Running(Some(pid)));
                 }
             }
-            println!("{:s}", hist);
         }
-    
-        // Change directories.
-        fn chdir(&mut self, cmd_line: &str) {
-            let mut argv: ~[~str] = split_words(cmd_line);
-            if argv.len() > 0 {
-                argv.remove(0);
-                os::change_dir(&Path::new(argv[0]));
+
+        // A job is about to have the terminal: note its modes, for
+        // end_fg_job to put back, whatever the job does to them.
+        fn save_modes(&mut self) {
+            if self.job_control {
+                self.shell_modes = terminal::get_modes();
+            }
+        }
+
+        // The foreground job is over: take the terminal back and restore
+        // the modes the shell had before it ran.
+        fn end_fg_job(&mut self) {
+            if self.fg_pgid.take().is_none() {
+                return;
+            }
+            self.tell(Running(None));
+            terminal::take_back();
+            match self.shell_modes {
+                Some(ref modes) => { terminal::set_modes(modes); }
+                None => { }
+            }
+        }
+
+        // Start a lone process of the foreground job.
+        fn parse_process(&mut self,
+                        cmd: ~Cmd,
+                        stdin: Option<i32>,
+                        stdout:Option<i32>) 
+                        -> Result<~Process, ShellError> {
+            // A group, or a conditional that is piped or redirected, runs in
+            // a subshell.
+            let (program, argv) = match (&cmd.group, &cmd.condition) {
+                (&Some(ref inner), _) => { (self_exe(), ~[~"-c", inner.to_owned()]) }
+                (_, &Some(ref inner)) => {
+                    (self_exe(), ~[~"-c", format!("[[ {:s} ]]", *inner)])
+                }
+                _ => { (cmd.program.to_owned(), cmd.argv.clone()) }
+            };
+            let subshell = !cmd.group.is_none() || !cmd.condition.is_none();
+            let path = match self.resolve(program) {
+                Ok(path) => { path }
+                Err(e)   => { return Err(e); }
+            };
+            let (program, argv) = self.launch(program, path, argv, self.fg_pgid, true);
+            let mut process = FgProcess::new(program, argv, stdin, stdout);
+            if subshell {
+                process.env = Some(self.subshell_env());
+            }
+            match process.run() {
+                Ok(process) => {
+                    self.join_fg_job(process.get_id());
+                    Ok(~process)
+                }
+                Err(e) => {
+                    Err(e)
+                }
+            }
+        }
+
+        // Under set -C, refuse to truncate an existing regular file, unless
+        // asked to with >|.
+        fn check_clobber(&self, path: &Path) -> Result<(), ShellError> {
+            if self.options.noclobber && path.is_file() {
+                return Err(RedirectFailed(format!("{}", path.display()),
+                                          ~"cannot overwrite existing file"));
+            }
+            Ok(())
+        }
+
+        // Find the program a command runs on $PATH, failing the way exec
+        // would without forking first.
+        fn resolve(&mut self, program: &str) -> Result<~str, ShellError> {
+            let path_var = self.lookup("PATH").unwrap_or(~"");
+            match self.commands.find(program, path_var) {
+                Some(path) => {
+                    Ok(format!("{}", path.display()))
+                }
+                None => {
+                    if program.contains_char('/') && Path::new(program).exists() {
+                        Err(NotExecutable(program.to_owned()))
+                    }
+                    else {
+                        Err(NotFound(program.to_owned()))
+                    }
+                }
+            }
+        }
+
+        // Report an error running a command and set $? to match. A command
+        // that wasn't found gets suggestions for what might have been meant.
+        fn fail(&mut self, e: ShellError) {
+            e.report();
+            self.last_status = e.status();
+            match e {
+                NotFound(ref program) if !program.contains_char('/') => {
+                    self.suggest(*program);
+                }
+                _ => {
+                }
+            }
+        }
+
+        // Suggest the builtins and programs on $PATH closest in spelling to
+        // name, e.g. "did you mean `git`?" for gti.
+        fn suggest(&self, name: &str) {
+            let limit = if name.char_len() <= 3 { 1 } else { 2 };
+            let path_var = self.lookup("PATH").unwrap_or(~"");
+            let mut candidates = program_names(path_var);
+            candidates.extend(&mut BUILTINS.iter().map(|name| name.to_owned()));
+            let mut best = limit + 1;
+            let mut matches: ~[~str] = ~[];
+            for candidate in candidates.move_iter() {
+                let distance = edit_distance(name, candidate);
+                if distance == 0 || distance > best {
+                    continue;
+                }
+                if distance < best {
+                    best = distance;
+                    matches = ~[];
+                }
+                if !matches.contains(&candidate) {
+                    matches.push(candidate);
+                }
+            }
+            matches.sort();
+            match matches.len() {
+                0 => {
+                }
+                1 => {
+                    print_err(format!("gash: did you mean `{:s}`?", matches[0]));
+                }
+                _ => {
+                    let quoted: ~[~str] = matches.iter().take(5)
+                        .map(|name| format!("`{:s}`", *name))
+                        .collect();
+                    print_err(format!("gash: did you mean one of {:s}?", quoted.connect(", ")));
+                }
+            }
+        }
+
+        // background processes.
+        fn make_bg_process(&mut self, cmd: ~str, argv: ~[~str], env: Option<~[(~str, ~str)]>) {
+            let program = match self.resolve(cmd) {
+                Ok(program) => { program }
+                Err(e)      => {
+                    self.fail(e);
+                    return;
+                }
+            };
+            let (cmd, argv) = self.launch(cmd, program, argv, None, false);
+            let mut process = BgProcess::new(cmd, argv);
+            process.capture = self.options.bgcapture;
+            process.env = env;
+            match process.run(self.jobs.exit_chan()) {
+                Ok(pid) => {
+                    self.own_group(pid);
+                    let id = self.jobs.add(self.current_line.clone(), ~process, Running);
+                    println!("[{:u}] {:i}", id, pid);
+                }
+                Err(e) => {
+                    self.fail(e);
+                }
+            }
+        }
+
+        // How to start program, found on $PATH for the command name, with
+        // argv. Without job control, and if everything is UTF-8, it is
+        // started directly: std::run makes argv[0] whatever it is asked to
+        // run, so a name typed without a '/' is run as it is, and looked up
+        // on the same $PATH again when the shell's is the environment's.
+        // Otherwise gash goes in between, in launch mode, so that the
+        // process is in its job's process group (pgid, or one of its own if
+        // None) and has the terminal if in the foreground before the
+        // program runs, and gets its arguments as bytes.
+        fn launch(&self, name: &str, program: ~str, argv: ~[~str], pgid: Option<pid_t>,
+                  foreground: bool) -> (~str, ~[~str]) {
+            // std::run can only pass on UTF-8, and gash execs with the
+            // bytes.
+            let direct = !self.job_control && !has_raw_bytes(program)
+                && !argv.iter().any(|arg| has_raw_bytes(*arg));
+            if direct && name == program.as_slice() {
+                return (program, argv);
+            }
+            if direct && !name.contains_char('/') && !self.vars.contains_key(&~"PATH") {
+                return (name.to_owned(), argv);
+            }
+            let group = if self.job_control { Some(pgid.unwrap_or(0)) } else { None };
+            let mut full = ~[name.to_owned()];
+            full.push_all_move(argv);
+            launcher(program, full, group, foreground)
+        }
+
+        // Background jobs get a process group of their own too, so that
+        // signals from the terminal don't reach them.
+        fn own_group(&mut self, pid: pid_t) {
+            if self.job_control {
+                terminal::set_pgid(pid, pid);
+            }
+        }
+
+        // Start a coprocess: a background job whose stdin and stdout are
+        // pipes back to the shell. Accepts `coproc NAME { cmd args }` and
+        // `coproc cmd args`, the latter named COPROC. The shell's ends of
+        // the pipes are exposed as ${NAME[0]} (read from the coprocess) and
+        // ${NAME[1]} (write to it), so e.g. `cat < /dev/fd/${NAME[0]}` works.
+        fn coproc(&mut self, cmd_line: &str) {
+            let rest = cmd_line.slice_from("coproc".len()).trim();
+            let (name, body) = match rest.find('{') {
+                Some(open) => {
+                    let name = rest.slice_to(open).trim();
+                    let body = rest.slice_from(open + 1);
+                    let body = match body.rfind('}') {
+                        Some(close) => { body.slice_to(close) }
+                        None        => { body }
+                    };
+                    let name = if name == "" { "COPROC" } else { name };
+                    (name.to_owned(), body.trim().trim_right_chars(&';').to_owned())
+                }
+                None => {
+                    (~"COPROC", rest.to_owned())
+                }
+            };
+            if self.coprocs.iter().any(|(_, n)| *n == name) {
+                print_err(format!("gash: coproc {:s} is still running", name));
+                self.last_status = 1;
+                return;
+            }
+            let mut argv = match self.expand(body) {
+                Some(body) => { split_words(body) }
+                None       => { return; }
+            };
+            if argv.len() == 0 {
+                print_err("gash: coproc: missing command");
+                self.last_status = 2;
+                return;
+            }
+            let program_name = argv.remove(0);
+            let program = match self.resolve(program_name) {
+                Ok(program) => { program }
+                Err(e)      => {
+                    self.fail(e);
+                    return;
+                }
+            };
+            let (program, argv) = self.launch(program_name, program, argv, None, false);
+            let mut process = BgProcess::coproc(program, argv);
+            match process.run(self.jobs.exit_chan()) {
+                Ok(pid) => {
+                    self.own_group(pid);
+                    self.vars.insert(name + "_PID", pid.to_str());
+                    self.vars.insert(name + "[0]", process.read_fd.unwrap().to_str());
+                    self.vars.insert(name + "[1]", process.write_fd.unwrap().to_str());
+                    self.coprocs.insert(pid, name);
+                    let id = self.jobs.add(cmd_line.to_owned(), ~process, Running);
+                    println!("[{:u}] {:i}", id, pid);
+                }
+                Err(e) => {
+                    self.fail(e);
+                }
+            }
+        }
+
+        // Forget the variables of a coprocess that has exited.
+        fn forget_coproc(&mut self, pid: i32) {
+            match self.coprocs.pop(&pid) {
+                Some(name) => {
+                    self.vars.remove(&(name + "_PID"));
+                    self.vars.remove(&(name + "[0]"));
+                    self.vars.remove(&(name + "[1]"));
+                }
+                None => {
+                }
+            }
+        }
+
+        // Look up a shell variable, falling back to the environment. $? is
+        // the last exit status, $- the letters of the options that are on
+        // (and i and m for an interactive shell with job control), and $$
+        // the shell's pid.
+        fn lookup(&self, name: &str) -> Option<~str> {
+            match name {
+                "?" => { return Some(self.last_status.to_str()); }
+                "-" => {
+                    let mut letters = self.options.letters();
+                    if self.interactive {
+                        letters.push_char('i');
+                    }
+                    if self.job_control {
+                        letters.push_char('m');
+                    }
+                    return Some(letters);
+                }
+                "$" => { return Some(unsafe { getpid() }.to_str()); }
+                _   => { }
+            }
+            match self.vars.find_equiv(&name) {
+                Some(value) => { Some(value.to_owned()) }
+                None        => { os::getenv(name) }
+            }
+        }
+
+        // Expand variable references in a command line, leaving the values
+        // quoted so that parsing it again can't find operators in them.
+        // Under set -u an unset variable is reported instead, and None
+        // returned. [[ ... ]] conditionals are left for conditional() to
+        // expand word by word, and ( ... ) groups for the subshell that runs
+        // them.
+        fn expand(&mut self, cmd_line: &str) -> Option<~str> {
+            let mut unset = None;
+            let mut line = ~"";
+            let mut start = 0;
+            let mut spans = Cmd::unexpanded(cmd_line);
+            spans.push((cmd_line.len(), cmd_line.len()));
+            for &(begin, end) in spans.iter() {
+                line.push_str(expand_words(cmd_line.slice(start, begin), |name| {
+                    let value = self.lookup(name);
+                    if value.is_none() && unset.is_none() {
+                        unset = Some(name.to_owned());
+                    }
+                    value
+                }));
+                line.push_str(cmd_line.slice(begin, end));
+                start = end;
+            }
+            match unset {
+                Some(name) if self.options.nounset => {
+                    print_err(format!("gash: {:s}: unbound variable", name));
+                    self.last_status = 1;
+                    None
+                }
+                _ => {
+                    Some(line)
+                }
+            }
+        }
+
+        // List jobs. -l adds pids, -p prints only pids, and -r/-s list
+        // only running/stopped jobs. Job spec arguments narrow the list down
+        // to those jobs. Finished jobs are forgotten once they are listed.
+        fn list_jobs(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            if argv.len() > 1 && argv[1] == ~"output" {
+                self.job_output(argv.slice_from(2));
+                return;
+            }
+            let mut long = false;
+            let mut pids_only = false;
+            let mut running_only = false;
+            let mut stopped_only = false;
+            let mut ids = ~[];
+            for arg in argv.slice_from(1).iter() {
+                if arg.starts_with("-") && arg.len() > 1 {
+                    for c in arg.slice_from(1).chars() {
+                        match c {
+                            'l' => { long = true; }
+                            'p' => { pids_only = true; }
+                            'r' => { running_only = true; }
+                            's' => { stopped_only = true; }
+                            _   => {
+                                print_err(format!("gash: jobs: -{}: invalid option", c));
+                                print_err("jobs: usage: jobs [-lprs] [jobspec ...]");
+                                self.last_status = 2;
+                                return;
+                            }
+                        }
+                    }
+                }
+                else {
+                    match self.find_job(Some(arg.as_slice()), "jobs") {
+                        Some(id) => { ids.push(id); }
+                        None     => { return; }
+                    }
+                }
+            }
+            self.jobs.reap();
+            if ids.len() == 0 {
+                ids = self.jobs.iter().map(|job| job.id).collect();
+            }
+            for &id in ids.iter() {
+                let job = self.jobs.get(id).unwrap();
+                if (running_only && job.state != Running)
+                        || (stopped_only && job.state != Stopped) {
+                    continue;
+                }
+                if pids_only {
+                    println!("{:d}", job.pid().unwrap_or(0));
+                }
+                else {
+                    println(self.jobs.describe(id, long));
+                }
+            }
+            // Finished jobs have now been reported.
+            for &id in ids.iter() {
+                if self.job_status(id).is_some() {
+                    self.remove_job(id);
+                }
+            }
+            self.last_status = 0;
+        }
+
+        // jobs output [jobspec]
+        // Print what a job has written, if its output was captured with
+        // `set -o bgcapture`. A job given by number can be asked about even
+        // after it has finished and left the table.
+        fn job_output(&mut self, args: &[~str]) {
+            self.jobs.reap();
+            let spec = args.iter().nth(0).map(|s| s.as_slice());
+            let numbered = spec.and_then(|spec| from_str::<uint>(spec.trim_left_chars(&'%')));
+            let id = match numbered {
+                Some(id) => { id }
+                None => {
+                    match self.find_job(spec, "jobs") {
+                        Some(id) => { id }
+                        None     => { return; }
+                    }
+                }
+            };
+            match self.jobs.output(id) {
+                Some(bytes) => {
+                    stdout().write(bytes);
+                    self.last_status = 0;
+                }
+                None => {
+                    print_err(format!("gash: jobs: %{:u}: no captured output", id));
+                    self.last_status = 1;
+                }
+            }
+        }
+
+        // Forget finished jobs, telling the user about the ones they
+        // haven't heard about yet, e.g.
+        //     [1]+  Done                    make
+        fn disown_dead(&mut self) {
+            self.jobs.reap();
+            let finished: ~[uint] = self.jobs.iter()
+                .filter(|job| job.state.is_finished())
+                .map(|job| job.id)
+                .collect();
+            for &id in finished.iter() {
+                if self.interactive && !self.jobs.get(id).unwrap().notified {
+                    println(self.jobs.describe(id, false));
+                }
+            }
+            for &id in finished.iter() {
+                self.remove_job(id);
+            }
+        }
+
+        // Drop a job from the table, closing any coprocess pipes it had.
+        fn remove_job(&mut self, id: uint) {
+            match self.jobs.remove(id) {
+                Some(mut job) => {
+                    job.process.close_pipes();
+                    match job.pid() {
+                        Some(pid) => { self.forget_coproc(pid); }
+                        None      => { }
+                    }
+                }
+                None => {
+                }
+            }
+        }
+
+        // set [-+bCefnuvx] [-+o option]
+        // Turns shell options on (-) or off (+). Letters can be grouped, as
+        // in `set -eu`. -o with no name lists the options, and +o lists
+        // them as the set commands that would restore them. With no
+        // arguments, lists the shell variables.
+        fn set_builtin(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            if argv.len() == 1 {
+                let mut names: ~[&~str] = self.vars.keys().collect();
+                names.sort();
+                for name in names.iter() {
+                    println!("{:s}={:s}", **name, *self.vars.get(*name));
+                }
+                self.last_status = 0;
+                return;
+            }
+            self.last_status = 0;
+            let args = argv.slice_from(1);
+            let mut i = 0;
+            while i < args.len() {
+                let arg = args[i].as_slice();
+                i += 1;
+                let on = arg.starts_with("-");
+                if arg.len() < 2 || !on && !arg.starts_with("+") {
+                    print_err(format!("gash: set: {:s}: invalid option", arg));
+                    self.last_status = 2;
+                    return;
+                }
+                for letter in arg.slice_from(1).chars() {
+                    let name = if letter == 'o' {
+                        if i == args.len() {
+                            self.list_set_options(on);
+                            continue;
+                        }
+                        i += 1;
+                        args[i - 1].to_owned()
+                    }
+                    else {
+                        match option_name(letter) {
+                            Some(name) => { name.to_owned() }
+                            None => {
+                                print_err(format!("gash: set: -{}: invalid option", letter));
+                                self.last_status = 2;
+                                return;
+                            }
+                        }
+                    };
+                    if SET_OPTIONS.iter().all(|option| option.name != name.as_slice()) {
+                        print_err(format!("gash: set: {:s}: invalid option name", name));
+                        self.last_status = 2;
+                        return;
+                    }
+                    self.options.set(name, on);
+                }
+            }
+        }
+
+        // List the `set` options as `set -o` does, or as `set +o` does:
+        // as commands.
+        fn list_set_options(&mut self, table: bool) {
+            for option in SET_OPTIONS.iter() {
+                let on = self.options.get(option.name).unwrap();
+                if table {
+                    println!("{:<15s}\t{:s}", option.name, if on { "on" } else { "off" });
+                }
+                else {
+                    println!("set {:s}o {:s}", if on { "-" } else { "+" }, option.name);
+                }
+            }
+        }
+
+        // shopt [-pqsu] [-o] [option ...]
+        // Set (-s) or unset (-u) the named options, or show them; with no
+        // names, all of them. -o works on the `set -o` options instead of
+        // the extended ones. -p shows options as shopt commands, and -q
+        // shows nothing: the status says whether all the options named are
+        // on.
+        fn shopt(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let mut set = None;
+            let mut print = false;
+            let mut quiet = false;
+            let mut set_options = false;
+            let mut names = ~[];
+            for arg in argv.slice_from(1).iter() {
+                if arg.starts_with("-") && arg.len() > 1 && names.len() == 0 {
+                    for c in arg.slice_from(1).chars() {
+                        match c {
+                            's' => { set = Some(true); }
+                            'u' => { set = Some(false); }
+                            'p' => { print = true; }
+                            'q' => { quiet = true; }
+                            'o' => { set_options = true; }
+                            _ => {
+                                print_err(format!("gash: shopt: -{}: invalid option", c));
+                                print_err("shopt: usage: shopt [-pqsu] [-o] [optname ...]");
+                                self.last_status = 2;
+                                return;
+                            }
+                        }
+                    }
+                }
+                else {
+                    names.push(arg.to_owned());
+                }
+            }
+            let known: ~[&'static str] = if set_options {
+                SET_OPTIONS.iter().map(|option| option.name).collect()
+            }
+            else {
+                SHOPT_OPTIONS.to_owned()
+            };
+            self.last_status = 0;
+            for name in names.iter() {
+                if !known.iter().any(|known| *known == name.as_slice()) {
+                    print_err(format!("gash: shopt: {:s}: invalid shell option name", *name));
+                    self.last_status = 1;
+                }
+            }
+            if self.last_status != 0 {
+                return;
+            }
+            match set {
+                Some(on) => {
+                    for name in names.iter() {
+                        self.options.set(*name, on);
+                    }
+                    return;
+                }
+                None => { }
+            }
+            // Only options asked about by name affect the status.
+            let named = names.len() > 0;
+            let shown: ~[~str] = if named {
+                names
+            }
+            else {
+                known.iter().map(|name| name.to_owned()).collect()
+            };
+            for name in shown.iter() {
+                let on = self.options.get(*name).unwrap();
+                if named && !on {
+                    self.last_status = 1;
+                }
+                if quiet {
+                    continue;
+                }
+                if print && set_options {
+                    println!("set {:s}o {:s}", if on { "-" } else { "+" }, *name);
+                }
+                else if print {
+                    println!("shopt -{} {:s}", if on { 's' } else { 'u' }, *name);
+                }
+                else {
+                    println!("{:<15s}\t{:s}", *name, if on { "on" } else { "off" });
+                }
+            }
+        }
+
+        // echo [-neE] [arg ...]
+        // Print the arguments, separated by spaces, and a newline unless -n
+        // is given. -e expands backslash escapes (\n, \t, \0nnn, \xHH, and
+        // \c to stop there); -E doesn't. shopt -s xpg_echo makes -e the
+        // default.
+        fn echo(&mut self, argv: &[~str]) -> ~str {
+            let mut newline = true;
+            let mut escapes = self.options.xpg_echo;
+            let mut args = argv.slice_from(1);
+            // Only words made up of these options are taken as options.
+            while args.len() > 0 {
+                let arg = args[0].as_slice();
+                if arg.len() < 2 || !arg.starts_with("-")
+                        || !arg.slice_from(1).chars().all(|c| "neE".contains_char(c)) {
+                    break;
+                }
+                for c in arg.slice_from(1).chars() {
+                    match c {
+                        'n' => { newline = false; }
+                        'e' => { escapes = true; }
+                        _   => { escapes = false; }
+                    }
+                }
+                args = args.slice_from(1);
+            }
+            let mut text = args.connect(" ");
+            if escapes {
+                let (expanded, stop) = echo_escapes(text);
+                text = expanded;
+                if stop {
+                    newline = false;
+                }
+            }
+            if newline {
+                text.push_char('\n');
+            }
+            self.last_status = 0;
+            text
+        }
+
+        // printf [-v var] format [arguments]
+        // Print the arguments under the control of format, as printf(1)
+        // does, using the format again while arguments are left. Besides
+        // C's conversions there are %b (an argument with echo -e escapes),
+        // %q (an argument quoted for the shell) and %(fmt)T (a time in
+        // seconds since the epoch, formatted by strftime; -1 means now and
+        // -2 when the shell started). -v puts the output in var instead.
+        fn printf_builtin(&mut self, argv: &[~str]) -> ~str {
+            let usage = "printf: usage: printf [-v var] format [arguments]";
+            let mut args = argv.slice_from(1);
+            let mut var = None;
+            if args.len() > 0 && args[0] == ~"-v" {
+                if args.len() < 2 {
+                    print_err("gash: printf: -v: option requires an argument");
+                    print_err(usage);
+                    self.last_status = 2;
+                    return ~"";
+                }
+                let name = args[1].clone();
+                if name.len() == 0 || name.char_at(0).is_digit()
+                        || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    print_err(format!("gash: printf: `{:s}': not a valid identifier", name));
+                    self.last_status = 2;
+                    return ~"";
+                }
+                var = Some(name);
+                args = args.slice_from(2);
+            }
+            if args.len() > 0 && args[0] == ~"--" {
+                args = args.slice_from(1);
+            }
+            if args.len() == 0 {
+                print_err(usage);
+                self.last_status = 2;
+                return ~"";
+            }
+            let printed = printf(args[0], args.slice_from(1), self.start_time);
+            for error in printed.errors.iter() {
+                print_err(*error);
+            }
+            self.last_status = if printed.failed { 1 } else { 0 };
+            match var {
+                Some(name) => {
+                    self.vars.insert(name, printed.output);
+                    ~""
+                }
+                None => {
+                    printed.output
+                }
+            }
+        }
+
+        // test expr, [ expr ]
+        // Evaluate a conditional expression: status 0 if it's true, 1 if
+        // it's false and 2 if it can't be read. The file tests are -b, -c,
+        // -d, -e, -f, -g, -G, -h/-L, -k, -N, -O, -p, -r, -s, -S, -t, -u, -w
+        // and -x, and a -nt, -ot and -ef b; strings have -z, -n, =, !=, <
+        // and >; integers -eq, -ne, -lt, -le, -gt and -ge. ! negates, -a
+        // and -o combine, and ( ) group.
+        fn test_builtin(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let name = argv[0].clone();
+            let mut args = argv.slice_from(1);
+            if name == ~"[" {
+                if args.len() == 0 || args[args.len() - 1] != ~"]" {
+                    print_err("gash: [: missing `]'");
+                    self.last_status = 2;
+                    return;
+                }
+                args = args.slice_to(args.len() - 1);
+            }
+            self.last_status = match test(args) {
+                Ok(true)  => { 0 }
+                Ok(false) => { 1 }
+                Err(e) => {
+                    print_err(format!("gash: {:s}: {:s}", name, e));
+                    2
+                }
+            };
+        }
+
+        // [[ expression ]]
+        // Like test, but the words aren't split after expansion; the right
+        // of == and != is a pattern (its quoted parts match literally), =~
+        // matches an extended regular expression and puts what matched in
+        // BASH_REMATCH, and && and || take the place of -a and -o.
+        fn conditional(&mut self, inner: &str) {
+            let outcome = condition(inner, |name| self.lookup(name));
+            self.last_status = match outcome {
+                Ok(outcome) => {
+                    match outcome.matches {
+                        Some(matches) => { self.set_rematch(matches); }
+                        None          => { }
+                    }
+                    if outcome.result { 0 } else { 1 }
+                }
+                Err(e) => {
+                    print_err(format!("gash: [[: {:s}", e));
+                    2
+                }
+            };
+        }
+
+        // Set BASH_REMATCH after a =~ match. gash has no arrays, so the
+        // parts are ${BASH_REMATCH[0]}, ${BASH_REMATCH[1]} and so on, the
+        // way coprocess fds are, with $BASH_REMATCH the whole match.
+        fn set_rematch(&mut self, matches: ~[~str]) {
+            let old: ~[~str] = self.vars.keys()
+                .filter(|name| name.starts_with("BASH_REMATCH"))
+                .map(|name| name.clone()).collect();
+            for name in old.iter() {
+                self.vars.remove(name);
+            }
+            for (i, part) in matches.iter().enumerate() {
+                self.vars.insert(format!("BASH_REMATCH[{}]", i), part.clone());
+            }
+            if matches.len() > 0 {
+                self.vars.insert(~"BASH_REMATCH", matches[0].clone());
+            }
+        }
+
+        // hash [-r] [-d] [name ...]
+        // With no arguments, list the remembered programs and how often
+        // each has been run. -r forgets them all; names are looked up and
+        // remembered, or forgotten with -d.
+        fn hash(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let mut delete = false;
+            let mut names = ~[];
+            for arg in argv.slice_from(1).iter() {
+                if *arg == ~"-r" {
+                    self.commands.clear();
+                }
+                else if *arg == ~"-d" {
+                    delete = true;
+                }
+                else if arg.starts_with("-") {
+                    print_err(format!("gash: hash: {:s}: invalid option", *arg));
+                    print_err("hash: usage: hash [-r] [-d] [name ...]");
+                    self.last_status = 2;
+                    return;
+                }
+                else {
+                    names.push(arg.to_owned());
+                }
+            }
+            self.last_status = 0;
+            if names.len() == 0 {
+                if argv.len() == 1 {
+                    if self.commands.is_empty() {
+                        print_err("hash: hash table empty");
+                        return;
+                    }
+                    println("hits\tcommand");
+                    for &(hits, ref path) in self.commands.list().iter() {
+                        println!("{:4u}\t{:s}", hits, *path);
+                    }
+                }
+                return;
+            }
+            let path_var = self.lookup("PATH").unwrap_or(~"");
+            for name in names.iter() {
+                let found = if delete {
+                    self.commands.forget(*name)
+                }
+                else {
+                    self.commands.add(*name, path_var)
+                };
+                if !found {
+                    print_err(format!("gash: hash: {:s}: not found", *name));
+                    self.last_status = 1;
+                }
+            }
+        }
+
+        // ulimit [-SHa] [-Rcdefilmnqrstuvx] [limit]
+        // Show or set resource limits for the shell and everything it runs.
+        // -S and -H pick the soft or hard limit; setting changes both unless
+        // one is picked. A limit is a number in the resource's unit,
+        // "unlimited", or "soft"/"hard" for the current value. With no
+        // resource, -f (file size) is meant.
+        fn ulimit(&mut self, cmd_line: &str) {
+            let usage = "ulimit: usage: ulimit [-SHa] [-Rcdefilmnqrstuvx] [limit]";
+            let argv = split_words(cmd_line);
+            let mut soft = false;
+            let mut hard = false;
+            let mut all = false;
+            let mut resources = ~[];
+            let mut value = None;
+            for arg in argv.slice_from(1).iter() {
+                if arg.starts_with("-") && arg.len() > 1 {
+                    for c in arg.slice_from(1).chars() {
+                        match c {
+                            'S' => { soft = true; }
+                            'H' => { hard = true; }
+                            'a' => { all = true; }
+                            _ => {
+                                match find_resource(c) {
+                                    Some(resource) => { resources.push(resource); }
+                                    None => {
+                                        print_err(format!("gash: ulimit: -{}: invalid option", c));
+                                        print_err(usage);
+                                        self.last_status = 2;
+                                        return;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                else if value.is_none() {
+                    value = Some(arg.to_owned());
+                }
+                else {
+                    print_err(usage);
+                    self.last_status = 2;
+                    return;
+                }
+            }
+            self.last_status = 0;
+            if all {
+                resources = RESOURCES.iter().collect();
+            }
+            else if resources.len() == 0 {
+                resources.push(find_resource('f').unwrap());
+            }
+            for resource in resources.iter() {
+                let (cur_soft, cur_hard) = match resource.get() {
+                    Ok(limits) => { limits }
+                    Err(e) => {
+                        print_err(format!("gash: ulimit: {:s}: cannot get limit: {:s}",
+                                          resource.name, e));
+                        self.last_status = 1;
+                        continue;
+                    }
+                };
+                let new = match value {
+                    None => {
+                        let shown = if hard && !soft { cur_hard } else { cur_soft };
+                        let shown = if shown == RLIM_INFINITY { ~"unlimited" } else { shown.to_str() };
+                        if all || resources.len() > 1 {
+                            println!("{:<36s} {:s}", resource.describe(), shown);
+                        }
+                        else {
+                            println(shown);
+                        }
+                        continue;
+                    }
+                    Some(ref value) if *value == ~"unlimited" => { RLIM_INFINITY }
+                    Some(ref value) if *value == ~"soft"      => { cur_soft }
+                    Some(ref value) if *value == ~"hard"      => { cur_hard }
+                    Some(ref value) => {
+                        match from_str::<u64>(*value) {
+                            Some(n) => { n }
+                            None => {
+                                print_err(format!("gash: ulimit: {:s}: invalid number", *value));
+                                self.last_status = 1;
+                                return;
+                            }
+                        }
+                    }
+                };
+                let both = !soft && !hard;
+                let result = resource.set(if soft || both { Some(new) } else { None },
+                                          if hard || both { Some(new) } else { None });
+                match result {
+                    Ok(()) => { }
+                    Err(e) => {
+                        print_err(format!("gash: ulimit: {:s}: cannot modify limit: {:s}",
+                                          resource.name, e));
+                        self.last_status = 1;
+                    }
+                }
+            }
+        }
+
+        // umask [-S] [mode]
+        // Show or set the file creation mask, which children inherit. The
+        // mode is octal, or symbolic like chmod's (u=rwx,g=rx,o=), and -S
+        // shows the mask symbolically.
+        fn umask(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let mut symbolic = false;
+            let mut mode = None;
+            for arg in argv.slice_from(1).iter() {
+                if *arg == ~"-S" {
+                    symbolic = true;
+                }
+                else if arg.starts_with("-") && mode.is_none() && arg.len() > 1
+                        && !arg.contains_char('=') {
+                    print_err(format!("gash: umask: {:s}: invalid option", *arg));
+                    print_err("umask: usage: umask [-S] [mode]");
+                    self.last_status = 2;
+                    return;
+                }
+                else {
+                    mode = Some(arg.to_owned());
+                }
+            }
+            self.last_status = 0;
+            let current = limits::get_umask();
+            match mode {
+                None => {
+                    if symbolic {
+                        println(limits::symbolic(current));
+                    }
+                    else {
+                        println!("{:04o}", current);
+                    }
+                }
+                Some(mode) => {
+                    let mask = if mode.chars().all(|c| c >= '0' && c <= '7') {
+                        from_str_radix::<u32>(mode, 8).filtered(|&mask| mask <= ALL_PERMISSIONS)
+                    }
+                    else {
+                        limits::parse_symbolic(mode, current)
+                    };
+                    match mask {
+                        Some(mask) => { limits::set_umask(mask); }
+                        None => {
+                            print_err(format!("gash: umask: {:s}: invalid mode", mode));
+                            self.last_status = 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // exec [redirection ...] [command [args]]
+        // Replace the shell with command. Redirections rewire the shell's
+        // own fds, for good if there is no command: [n]>file, [n]>>file,
+        // [n]<file, [n]>&m, [n]<&m, [n]>&- to close, and &>file for both
+        // stdout and stderr. Returns false if the shell should exit, which a
+        // script does when the command can't be run.
+        fn exec(&mut self, cmd_line: &str) -> bool {
+            let words = match self.expand(cmd_line.slice_from("exec".len())) {
+                Some(words) => { split_words(words) }
+                None        => { return self.interactive; }
+            };
+            let mut argv = ~[];
+            let mut words = words.move_iter();
+            self.last_status = 0;
+            loop {
+                let word = match words.next() {
+                    Some(word) => { word }
+                    None       => { break; }
+                };
+                let (fd, op, target) = match parse_redirection(word) {
+                    Some(redirection) => { redirection }
+                    None => {
+                        argv.push(word);
+                        continue;
+                    }
+                };
+                let target = if target.len() > 0 {
+                    target
+                }
+                else {
+                    match words.next() {
+                        Some(target) => { target }
+                        None => {
+                            print_err("gash: exec: syntax error: expected a file name");
+                            self.last_status = 2;
+                            return true;
+                        }
+                    }
+                };
+                let output = O_WRONLY | O_CREAT | O_TRUNC;
+                let result = match op.as_slice() {
+                    ">"  => {
+                        self.check_clobber(&Path::new(target.as_slice()))
+                            .and_then(|_| redirect_fd(fd.unwrap_or(STDOUT_FILENO), target, output))
+                    }
+                    ">|" => { redirect_fd(fd.unwrap_or(STDOUT_FILENO), target, output) }
+                    ">>" => { redirect_fd(fd.unwrap_or(STDOUT_FILENO), target, O_WRONLY | O_CREAT | O_APPEND) }
+                    "<"  => { redirect_fd(fd.unwrap_or(STDIN_FILENO), target, O_RDONLY) }
+                    "&>" => {
+                        self.check_clobber(&Path::new(target.as_slice()))
+                            .and_then(|_| redirect_fd(STDOUT_FILENO, target, output))
+                            .and_then(|_| duplicate_fd(STDERR_FILENO, STDOUT_FILENO))
+                    }
+                    _ => {
+                        let fd = fd.unwrap_or(if op == ~"<&" { STDIN_FILENO } else { STDOUT_FILENO });
+                        if target == ~"-" {
+                            close_fd(fd);
+                            Ok(())
+                        }
+                        else {
+                            match from_str::<c_int>(target) {
+                                Some(target) => { duplicate_fd(fd, target) }
+                                None => {
+                                    print_err(format!("gash: exec: {:s}: ambiguous redirect", target));
+                                    self.last_status = 1;
+                                    return true;
+                                }
+                            }
+                        }
+                    }
+                };
+                match result {
+                    Ok(()) => { }
+                    Err(e) => {
+                        self.fail(e);
+                        return true;
+                    }
+                }
+            }
+            if argv.len() == 0 {
+                return true;
+            }
+            let e = match self.resolve(argv[0]) {
+                Ok(program) => { exec_program(program, argv) }
+                Err(e)      => { e }
+            };
+            self.fail(e);
+            self.interactive
+        }
+
+        // trap [-lp] [[action] signal ...]
+        // Run action when a signal arrives, or for the pseudo-signals EXIT,
+        // ERR and DEBUG. An empty action ignores the signal, and "-" (or no
+        // action) puts back its usual handling. -p, or no arguments, lists
+        // the traps; -l lists the signals. The action is kept as typed, so
+        // variables in it are expanded when it runs.
+        fn trap_builtin(&mut self, cmd_line: &str) {
+            let rest = cmd_line.slice_from("trap".len()).trim_left();
+            let rest = if rest.starts_with("-- ") { rest.slice_from(3).trim_left() } else { rest };
+            let (first, rest) = match rest.chars().next() {
+                Some(quote) if quote == '\'' || quote == '"' => {
+                    match rest.slice_from(1).find(quote) {
+                        Some(close) => {
+                            (Some(rest.slice(1, close + 1).to_owned()),
+                             rest.slice_from(close + 2))
+                        }
+                        None => {
+                            print_err("gash: trap: unterminated quote");
+                            self.last_status = 2;
+                            return;
+                        }
+                    }
+                }
+                Some(_) => {
+                    let word = rest.words().nth(0).unwrap();
+                    (Some(word.to_owned()), rest.slice_from(word.len()))
+                }
+                None => {
+                    (None, rest)
+                }
+            };
+            let specs: ~[&str] = rest.words().collect();
+            self.last_status = 0;
+            match first {
+                None => {
+                    self.list_traps(specs);
+                }
+                Some(ref flag) if *flag == ~"-p" => {
+                    self.list_traps(specs);
+                }
+                Some(ref flag) if *flag == ~"-l" => {
+                    self.list_signals(&[]);
+                }
+                Some(action) => {
+                    // A lone signal, or "-", resets.
+                    let (action, specs) = if specs.len() == 0 {
+                        (None, ~[action.as_slice()])
+                    }
+                    else if action == ~"-" {
+                        (None, specs)
+                    }
+                    else {
+                        (Some(action.clone()), specs)
+                    };
+                    for spec in specs.iter() {
+                        match trap_number(*spec) {
+                            Some(number) => {
+                                match self.set_trap(number, action.clone()) {
+                                    Ok(()) => { }
+                                    Err(e) => {
+                                        print_err(format!("gash: trap: {:s}: {:s}", *spec, e));
+                                        self.last_status = 1;
+                                    }
+                                }
+                            }
+                            None => {
+                                print_err(format!("gash: trap: {:s}: invalid signal specification",
+                                                  *spec));
+                                self.last_status = 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Set or (with None) reset the trap for number, or say why it can't
+        // be set. KILL and STOP can't be caught, and the shell needs CHLD
+        // itself: the runtime hears of its children's exits through it.
+        fn set_trap(&mut self, number: int, action: Option<~str>) -> Result<(), ~str> {
+            if action.is_some() {
+                if number == RETURN_TRAP {
+                    return Err(~"not supported: gash has no functions or sourced scripts");
+                }
+                if number == SIGKILL as int || number == SIGSTOP as int {
+                    return Err(~"cannot be trapped or ignored");
+                }
+                if number == SIGCHLD as int {
+                    return Err(~"cannot be trapped: the shell needs it to wait for its children");
+                }
+            }
+            if number > 0 {
+                let sig = number as i32;
+                let old = match action {
+                    Some(ref action) if *action == ~"" => { Some(signals::ignore(sig)) }
+                    Some(_) if self.interactive && sig == SIGINT => {
+                        // Ctrl-C already reaches us through the interrupt
+                        // handler, which check_interrupt hears from.
+                        None
+                    }
+                    Some(_) => { Some(signals::trap(sig)) }
+                    None => {
+                        match self.saved_handlers.pop(&number) {
+                            Some(handler) => { signals::restore(sig, handler); }
+                            None          => { }
+                        }
+                        None
+                    }
+                };
+                match old {
+                    Some(handler) if !self.saved_handlers.contains_key(&number) => {
+                        self.saved_handlers.insert(number, handler);
+                    }
+                    _ => { }
+                }
+            }
+            match action {
+                Some(action) => { self.traps.insert(number, action); }
+                None         => { self.traps.remove(&number); }
+            }
+            Ok(())
+        }
+
+        // Print the traps, or those for the given specs, as commands that
+        // would set them again.
+        fn list_traps(&mut self, specs: &[&str]) {
+            let mut numbers: ~[int] = if specs.len() == 0 {
+                self.traps.keys().map(|&number| number).collect()
+            }
+            else {
+                let mut numbers = ~[];
+                for spec in specs.iter() {
+                    match trap_number(*spec) {
+                        Some(number) => { numbers.push(number); }
+                        None => {
+                            print_err(format!("gash: trap: {:s}: invalid signal specification", *spec));
+                            self.last_status = 1;
+                        }
+                    }
+                }
+                numbers
+            };
+            numbers.sort();
+            for number in numbers.iter() {
+                match self.traps.find(number) {
+                    Some(action) => {
+                        println!("trap -- '{:s}' {:s}", action.replace("'", "'\\''"),
+                                 trap_name(*number));
+                    }
+                    None => {
+                    }
+                }
+            }
+        }
+
+        // Run the trap for number, if there is one, keeping $? as it was.
+        // Returns false if the trap exited the shell.
+        fn run_trap(&mut self, number: int) -> bool {
+            if self.in_trap {
+                return true;
+            }
+            let action = match self.traps.find(&number) {
+                Some(action) if action.len() > 0 => { action.clone() }
+                _ => { return true; }
+            };
+            let status = self.last_status;
+            self.in_trap = true;
+            let keep_going = self.run_line(action);
+            self.in_trap = false;
+            if keep_going {
+                self.last_status = status;
+            }
+            keep_going
+        }
+
+        // Run the traps for signals that have arrived since the last look.
+        // Returns false if one of them exited the shell.
+        fn run_traps(&mut self) -> bool {
+            let mut numbers: ~[int] = signals::take_pending().iter().map(|&sig| sig as int).collect();
+            numbers.push_all_move(replace(&mut self.pending_traps, ~[]));
+            for &number in numbers.iter() {
+                if !self.run_trap(number) {
+                    return false;
+                }
+            }
+            true
+        }
+
+        // The shell is about to exit: run the EXIT trap.
+        pub fn leave(&mut self) {
+            self.run_trap(EXIT_TRAP);
+        }
+
+        // Drop a finished job whose status has been handed over by fg or
+        // wait, so that it can't be waited for again.
+        fn consume_job(&mut self, id: uint) {
+            let pid = self.jobs.get(id).and_then(|job| job.pid());
+            self.remove_job(id);
+            match pid {
+                Some(pid) => { self.jobs.take_status(pid); }
+                None      => { }
+            }
+        }
+
+        // exit [N]
+        // Returns true if the shell should exit, with status N or else $?.
+        // An interactive shell with jobs left warns instead, unless this
+        // exit straight follows that warning.
+        fn exit(&mut self, cmd_line: &str, warned: bool) -> bool {
+            let argv = split_words(cmd_line);
+            if argv.len() > 2 {
+                print_err("gash: exit: too many arguments");
+                self.last_status = 1;
+                return false;
+            }
+            if self.interactive && !warned && self.warn_jobs() {
+                return false;
+            }
+            match argv.iter().nth(1) {
+                Some(arg) => {
+                    match from_str::<int>(*arg) {
+                        Some(status) => { self.last_status = status & 0xff; }
+                        None => {
+                            print_err(format!("gash: exit: {:s}: numeric argument required", *arg));
+                            self.last_status = 2;
+                        }
+                    }
+                }
+                None => {
+                }
+            }
+            self.hang_up_jobs();
+            true
+        }
+
+        // Warn that there are jobs which exiting would hang up. Returns
+        // true if there were any. shopt -u checkjobs turns this off.
+        fn warn_jobs(&mut self) -> bool {
+            if !self.options.checkjobs {
+                return false;
+            }
+            self.jobs.reap();
+            let live = self.jobs.iter()
+                .count(|job| !job.state.is_finished() && !job.nohup);
+            if live == 0 {
+                return false;
+            }
+            if self.jobs.iter().any(|job| job.state == Stopped && !job.nohup) {
+                print_err("There are stopped jobs.");
+            }
+            else {
+                print_err("There are running jobs.");
+            }
+            self.exit_warned = true;
+            true
+        }
+
+        // Send SIGHUP to the jobs as an interactive shell exits, the way a
+        // closing terminal would, and SIGCONT after it to stopped ones so
+        // that they see it. Jobs marked with `disown -h` are left alone,
+        // and shopt -u huponexit leaves them all.
+        fn hang_up_jobs(&mut self) {
+            if !self.interactive || !self.options.huponexit {
+                return;
+            }
+            self.jobs.reap();
+            for job in self.jobs.iter() {
+                if job.state.is_finished() || job.nohup {
+                    continue;
+                }
+                // Kill perhaps isn't the best way to do this, but without it
+                // we have no way of reaching our background processes with
+                // only the PID - we'd need the process object itself, and
+                // that can't be moved out of the spawn() that it's trapped
+                // inside.
+                let target = if self.job_control {
+                    job.group().map(|pgid| -pgid)
+                }
+                else {
+                    job.pid()
+                };
+                match target {
+                    Some(target) => unsafe {
+                        kill(target, SIGHUP);
+                        if job.state == Stopped {
+                            kill(target, SIGCONT);
+                        }
+                    },
+                    None => {
+                    }
+                }
+            }
+        }
+
+        // disown [-h] [-ar] [jobspec ...]
+        // Remove jobs from the table, so the shell neither reports nor hangs
+        // them up; with -h they stay but are only spared the SIGHUP. With no
+        // job specs the current job is meant, or every job with -a (running
+        // ones only with -r).
+        fn disown(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let mut keep = false;
+            let mut all = false;
+            let mut running_only = false;
+            let mut ids = ~[];
+            for arg in argv.slice_from(1).iter() {
+                if arg.starts_with("-") && arg.len() > 1 {
+                    for c in arg.slice_from(1).chars() {
+                        match c {
+                            'h' => { keep = true; }
+                            'a' => { all = true; }
+                            'r' => { running_only = true; }
+                            _   => {
+                                print_err(format!("gash: disown: -{}: invalid option", c));
+                                print_err("disown: usage: disown [-h] [-ar] [jobspec ...]");
+                                self.last_status = 2;
+                                return;
+                            }
+                        }
+                    }
+                }
+                else {
+                    match self.find_job(Some(arg.as_slice()), "disown") {
+                        Some(id) => { ids.push(id); }
+                        None     => { return; }
+                    }
+                }
+            }
+            if ids.len() == 0 {
+                if all || running_only {
+                    ids = self.jobs.iter()
+                        .filter(|job| !running_only || job.state == Running)
+                        .map(|job| job.id)
+                        .collect();
+                }
+                else {
+                    match self.find_job(None, "disown") {
+                        Some(id) => { ids.push(id); }
+                        None     => { return; }
+                    }
+                }
+            }
+            for &id in ids.iter() {
+                if keep {
+                    self.jobs.get_mut(id).unwrap().nohup = true;
+                }
+                else {
+                    self.remove_job(id);
+                }
+            }
+            self.last_status = 0;
+        }
+
+        // Push a new command onto history.
+        fn push_hist(&mut self, cmd_line: &str) {
+            &self.history.push(cmd_line.to_owned());
+        }
+
+        // Show the history.
+        fn show_hist(&mut self) {
+            let mut hist = ~"";
+            for i in self.history.iter() {
+                let s = i.to_owned();
+                match hist {
+                    ~"" => { hist = s; }
+                    _   => { hist = hist + "\n" + s; }
+                }
+            }
+            println!("{:s}", hist);
+        }
+    
+        // cd [dir]
+        // Change directories, to $HOME if no directory is given.
+        fn chdir(&mut self, cmd_line: &str) {
+            let argv = split_words(cmd_line);
+            let dir = match argv.iter().nth(1) {
+                Some(dir) => { dir.clone() }
+                None => {
+                    match self.lookup("HOME") {
+                        Some(home) => { home }
+                        None => {
+                            print_err("gash: cd: HOME not set");
+                            self.last_status = 1;
+                            return;
+                        }
+                    }
+                }
+            };
+            if !os::change_dir(&Path::new(encode_bytes(dir))) {
+                print_err(format!("gash: cd: {:s}: {:s}", dir, os::last_os_error()));
+                self.last_status = 1;
+                return;
             }
+            self.last_status = 0;
         }
     }
 }
@@ -2,6 +2,9 @@
 pub mod fg{
     use std::run::Process;
     use std::run::ProcessOptions;
+    use std::libc::consts::os::posix88::STDERR_FILENO;
+    use helpers::helpers::spawn_process;
+    use errors::errors::ShellError;
     // A foreground process is a command, arguments, and file descriptors for its
     // input and output.
     
@@ -12,6 +15,8 @@ pub mod fg{
         args        : ~[~str],
         stdin       : Option<i32>,
         stdout      : Option<i32>,
+        // The environment to run with, if not the shell's.
+        env         : Option<~[(~str, ~str)]>,
     }
     impl FgProcess {
         pub fn new(program : ~str, argv: ~[~str],
@@ -23,20 +28,22 @@ pub mod fg{
                 args        : argv.to_owned(),
                 stdin       : stdin,
                 stdout      : stdout,
+                env         : None,
             }
         }
         
-        pub fn run(&mut self) -> Process {
+        pub fn run(&mut self) -> Result<Process, ShellError> {
             let command = self.command.to_owned();
             let args = self.args.to_owned();
             let options = ProcessOptions {
-                env    : None,
+                env    : self.env.clone(),
                 dir    : None,
                 in_fd  : self.stdin,
                 out_fd : self.stdout,
-                err_fd : None,
+                // The shell's own stderr, wherever exec may have sent it.
+                err_fd : Some(STDERR_FILENO),
             };
-            Process::new(command, args, options).expect("Couldn't run!")
+            spawn_process(command, args, options)
         }
     }
 }
@@ -44,68 +51,389 @@ pub mod fg{
 #[allow(dead_code)]
 pub mod bg {
     // Background processes are handled differently, but not *that* differently:
-    // we need to keep track of an ProcessExit port for determining whether a
-    // process has finished running, as well as a pid for the process (for killing
-    // it when the shell terminates).
+    // a task waits for each one and sends its pid and ProcessExit down a
+    // channel shared by all the jobs, so the shell learns of exits as they
+    // happen, and we keep a pid for the process (for killing it when the shell
+    // terminates).
 
     // Note: std::run is replaced with std::io::process in the latest Rust
     // release.
-    use std::run::Process;
-    use std::run::ProcessOptions;
+    use std::run::{Process, ProcessOptions};
     use std::io::process::ProcessExit;
+    use std::io::pipe::PipeStream;
+    use std::io::io_error;
+    use std::c_str::ToCStr;
+    use std::comm::SharedChan;
     use std::libc::types::os::arch::posix88::pid_t;
+    use std::libc::consts::os::posix88::{O_RDONLY, STDOUT_FILENO, STDERR_FILENO};
+    use std::libc::c_int;
+    use std::libc;
+    use std::os;
+    use extra::ringbuf::RingBuf;
+    use helpers::helpers::spawn_process;
+    use errors::errors::ShellError;
+    use terminal::terminal::Termios;
     pub struct BgProcess {
         command      : ~str,
         args         : ~[~str],
-        exit_port    : Option<Port<ProcessExit>>,
         pid          : Option<i32>,
         stdin       : Option<i32>,
         stdout      : Option<i32>,
+        // The shell's ends of a coprocess's pipes: read_fd receives the
+        // child's output and write_fd feeds its input.
+        read_fd      : Option<c_int>,
+        write_fd     : Option<c_int>,
+        // Process group to signal, if it isn't just pid.
+        pgid         : Option<pid_t>,
+        // Terminal modes the job had when it was stopped, restored by fg.
+        modes        : Option<Termios>,
+        // Whether to capture the job's output instead of letting it go to
+        // the terminal, and the capture once it has started.
+        capture      : bool,
+        output       : Option<Capture>,
+        // The environment to run with, if not the shell's.
+        env          : Option<~[(~str, ~str)]>,
+    }
+
+    // How much of a job's output is kept when it is captured.
+    static CAPTURE_LIMIT : uint = 64 * 1024;
+
+    // The last CAPTURE_LIMIT bytes a job has written to stdout and stderr.
+    // A task reads them from the job's end of a pipe and sends them here.
+    pub struct Capture {
+        port   : Port<~[u8]>,
+        buffer : RingBuf<u8>,
+    }
+
+    impl Capture {
+        // Start capturing whatever is written to the pipe fd.
+        fn new(fd: c_int) -> Capture {
+            let (port, chan): (Port<~[u8]>, Chan<~[u8]>) = Chan::new();
+            spawn(proc() {
+                let mut stream = match PipeStream::open(fd) {
+                    Some(stream) => { stream }
+                    None         => { return; }
+                };
+                let mut buf = [0u8, ..4096];
+                // End of file is raised as an io_error too.
+                io_error::cond.trap(|_| { }).inside(|| {
+                    loop {
+                        match stream.read(buf) {
+                            Some(n) => { chan.try_send(buf.slice_to(n).to_owned()); }
+                            None    => { break; }
+                        }
+                    }
+                });
+            });
+            Capture {
+                port: port,
+                buffer: RingBuf::new(),
+            }
+        }
+
+        // Move what has arrived so far into the buffer, dropping the oldest
+        // bytes beyond the limit.
+        pub fn drain(&mut self) {
+            loop {
+                let bytes = match self.port.try_recv() {
+                    Some(bytes) => { bytes }
+                    None        => { return; }
+                };
+                for &byte in bytes.iter() {
+                    if self.buffer.len() == CAPTURE_LIMIT {
+                        self.buffer.pop_front();
+                    }
+                    self.buffer.push_back(byte);
+                }
+            }
+        }
+
+        pub fn contents(&mut self) -> ~[u8] {
+            self.drain();
+            self.buffer.iter().map(|&byte| byte).collect()
+        }
     }
     impl BgProcess {
         pub fn new(program : ~str, argv: ~[~str]) -> BgProcess {
             BgProcess {
                 command: program.to_owned(),
                 args: argv.to_owned(),
-                exit_port: None,
                 pid: None,
                 stdin: None,
                 stdout: None,
+                read_fd: None,
+                write_fd: None,
+                pgid: None,
+                modes: None,
+                capture: false,
+                output: None,
+                env: None,
             }
         }
 
-        pub fn run(&mut self) -> Option<pid_t> {
-            // Process exit ports; used for checking dead status.
-            let (port, chan): (Port<ProcessExit>, Chan<ProcessExit>) = Chan::new();
+        // Turn the processes of a foreground job that was stopped into a
+        // job. Tasks take the processes over and wait for them, the same as
+        // for jobs started in the background, each reaping its own as soon
+        // as it exits; the job's exit is that of the last one.
+        pub fn from_processes(command: ~str, processes: ~[~Process], pgid: pid_t,
+                              exits: SharedChan<(pid_t, ProcessExit)>) -> BgProcess {
+            let pid = processes.last().get_id();
+            let mut job = BgProcess::new(command, ~[]);
+            job.pid = Some(pid);
+            job.pgid = Some(pgid);
+            for process in processes.move_iter() {
+                let exits = exits.clone();
+                spawn(proc() {
+                    let mut process = process;
+                    let exit = process.finish();
+                    if process.get_id() == pid {
+                        exits.try_send_deferred((pid, exit));
+                    }
+                });
+            }
+            job
+        }
+
+        // The process group signals for this job go to.
+        pub fn group(&self) -> Option<pid_t> {
+            self.pgid.or(self.pid)
+        }
+
+        // A coprocess is a background process with two pipes tied to it,
+        // one for each direction.
+        pub fn coproc(program : ~str, argv: ~[~str]) -> BgProcess {
+            let to_child = os::pipe();
+            let from_child = os::pipe();
+            let mut process = BgProcess::new(program, argv);
+            process.stdin = Some(to_child.input);
+            process.stdout = Some(from_child.out);
+            process.read_fd = Some(from_child.input);
+            process.write_fd = Some(to_child.out);
+            process
+        }
+
+        // Close the shell's ends of the coprocess pipes, if there are any.
+        pub fn close_pipes(&mut self) {
+            for fd in [self.read_fd.take(), self.write_fd.take()].iter() {
+                match *fd {
+                    Some(fd) => unsafe { libc::close(fd); },
+                    None => { }
+                }
+            }
+        }
+
+        // The child's ends of the coprocess pipes belong to the child once
+        // it has been spawned, so the shell lets go of its copies.
+        fn close_child_ends(&mut self) {
+            if self.read_fd.is_none() && self.write_fd.is_none() {
+                return;
+            }
+            for fd in [self.stdin.take(), self.stdout.take()].iter() {
+                match *fd {
+                    Some(fd) => unsafe { libc::close(fd); },
+                    None => { }
+                }
+            }
+        }
+
+        // Start the process. Its exit is sent down exits once it finishes.
+        // Unless it has input of its own it reads from /dev/null, so that
+        // it doesn't compete with the shell for the terminal.
+        pub fn run(&mut self, exits: SharedChan<(pid_t, ProcessExit)>)
+                -> Result<pid_t, ShellError> {
+            let null = if self.stdin.is_none() {
+                let fd = "/dev/null".with_c_str(|path| unsafe { libc::open(path, O_RDONLY, 0) });
+                if fd < 0 { None } else { Some(fd) }
+            }
+            else {
+                None
+            };
+            let capture = if self.capture { Some(os::pipe()) } else { None };
+            let stdin = self.stdin.or(null);
+            let (stdout, stderr) = match capture {
+                Some(ref pipe) => { (Some(pipe.out), Some(pipe.out)) }
+                None => { (self.stdout.or(Some(STDOUT_FILENO)), Some(STDERR_FILENO)) }
+            };
             // Process ports; these don't leave this function and are used for
             // sending the PID out in the return value.
-            let (pidport, pidchan): (Port<Option<pid_t>>, Chan<Option<pid_t>>) 
+            let (pidport, pidchan): (Port<Result<pid_t, ShellError>>,
+                                     Chan<Result<pid_t, ShellError>>)
                                 = Chan::new();
             let command = self.command.to_owned();
             let args = self.args.to_owned();
+            let env = self.env.clone();
             spawn(proc() { 
                 let options = ProcessOptions {
-                    env    : None,
+                    env    : env,
                     dir    : None,
-                    in_fd  : None,
-                    out_fd : None,
-                    err_fd : None,
+                    in_fd  : stdin,
+                    out_fd : stdout,
+                    err_fd : stderr,
                 };
-                let maybe_process = Process::new(command, args, options);
+                let maybe_process = spawn_process(command, args, options);
                 match maybe_process {
-                    Some(mut process) => {
+                    Ok(mut process) => {
                         // Send the pid out for the return value
-                        pidchan.try_send_deferred(Some(process.get_id()));
-                        chan.try_send_deferred(process.finish());
+                        let pid = process.get_id();
+                        pidchan.try_send_deferred(Ok(pid));
+                        exits.try_send_deferred((pid, process.finish()));
                     }
-                    None => {
-                        pidchan.try_send_deferred(None);
+                    Err(e) => {
+                        pidchan.try_send_deferred(Err(e));
                     }
                 }
             });
-            self.exit_port = Some(port);
-            self.pid = pidport.recv();
-            self.pid
+            let result = pidport.recv();
+            self.pid = match result {
+                Ok(pid) => { Some(pid) }
+                Err(_)  => { None }
+            };
+            self.close_child_ends();
+            if self.pid.is_none() {
+                self.close_pipes();
+            }
+            // The child has its own copies of these now.
+            match null {
+                Some(fd) => unsafe { libc::close(fd); },
+                None => { }
+            }
+            match capture {
+                Some(pipe) => {
+                    unsafe { libc::close(pipe.out); }
+                    if self.pid.is_some() {
+                        self.output = Some(Capture::new(pipe.input));
+                    }
+                    else {
+                        unsafe { libc::close(pipe.input); }
+                    }
+                }
+                None => {
+                }
+            }
+            result
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub mod wait {
+    // Waiting for a child to stop or exit without reaping it. The runtime
+    // reaps children itself when Process::finish is called, so we only peek
+    // (WNOWAIT) to learn whether the child exited or was stopped.
+    use std::libc::{c_int, pid_t};
+    use std::libc::consts::os::posix88::EINTR;
+    use std::io::timer;
+    use std::os;
+
+    // siginfo_t from <signal.h> on Linux, as filled in for SIGCHLD.
+    struct SigInfo {
+        si_signo  : c_int,
+        si_errno  : c_int,
+        si_code   : c_int,
+        _pad      : c_int,
+        si_pid    : c_int,
+        si_uid    : u32,
+        si_status : c_int,
+        _rest     : [u8, ..100],
+    }
+
+    static P_PID       : c_int = 1;
+    static P_PGID      : c_int = 2;
+    static WNOHANG     : c_int = 1;
+    static WSTOPPED    : c_int = 2;
+    static WEXITED     : c_int = 4;
+    static WNOWAIT     : c_int = 0x01000000;
+    static CLD_STOPPED : c_int = 5;
+
+    extern {
+        fn waitid(idtype: c_int, id: pid_t, info: *mut SigInfo, options: c_int) -> c_int;
+    }
+
+    #[deriving(Eq)]
+    pub enum Change {
+        Exited,
+        Stopped,
+    }
+
+    // Block until a child picked out by idtype and id exits or is stopped,
+    // and say which child and how. None if there is no such child left to
+    // wait for.
+    fn peek(idtype: c_int, id: pid_t) -> Option<(pid_t, Change)> {
+        let (ret, info) = call_waitid(idtype, id, WEXITED | WSTOPPED | WNOWAIT);
+        if ret != 0 {
+            None
+        }
+        else if info.si_code == CLD_STOPPED {
+            Some((info.si_pid, Stopped))
+        }
+        else {
+            Some((info.si_pid, Exited))
+        }
+    }
+
+    // True if a process in the group pgid is stopped, without blocking.
+    pub fn is_stopped(pgid: pid_t) -> bool {
+        let (ret, info) = call_waitid(P_PGID, pgid, WSTOPPED | WNOWAIT | WNOHANG);
+        // With WNOHANG and nothing to report, the pid is left as zero.
+        ret == 0 && info.si_pid != 0
+    }
+
+    // waitid, retried if interrupted by a signal.
+    fn call_waitid(idtype: c_int, id: pid_t, options: c_int) -> (c_int, SigInfo) {
+        let mut info = SigInfo {
+            si_signo: 0,
+            si_errno: 0,
+            si_code: 0,
+            _pad: 0,
+            si_pid: 0,
+            si_uid: 0,
+            si_status: 0,
+            _rest: [0, ..100],
+        };
+        let mut ret;
+        loop {
+            ret = unsafe {
+                waitid(idtype, id, &mut info, options)
+            };
+            if ret == 0 || os::errno() as c_int != EINTR {
+                break;
+            }
+        }
+        (ret, info)
+    }
+
+    // Block until pid either exits or is stopped. A pid that can't be
+    // waited for (because it has already been reaped) counts as exited.
+    pub fn wait_for_change(pid: pid_t) -> Change {
+        match peek(P_PID, pid) {
+            Some((_, Stopped)) => { Stopped }
+            _                  => { Exited }
+        }
+    }
+
+    // Block until some process in the group pgid exits or is stopped, and
+    // say which. None if none of the group is left to wait for.
+    pub fn wait_for_group(pgid: pid_t) -> Option<(pid_t, Change)> {
+        peek(P_PGID, pgid)
+    }
+
+    // Block until a job, whose processes are in the group pgid, either has
+    // one of them stopped or sees its last one, last, exit. The others
+    // exiting are left for the tasks finishing them, which reap them;
+    // until one has, it is looked at again after a moment.
+    pub fn wait_for_job(pgid: pid_t, last: pid_t) -> Change {
+        loop {
+            match wait_for_group(pgid) {
+                Some((_, Stopped)) => {
+                    return Stopped;
+                }
+                Some((pid, Exited)) if pid != last => {
+                    timer::sleep(1);
+                }
+                _ => {
+                    return Exited;
+                }
+            }
         }
     }
 }
@@ -0,0 +1,145 @@
+#[allow(dead_code)]
+pub mod signals {
+    use std::libc::{c_int, pid_t, size_t};
+    use std::ascii::StrAsciiExt;
+
+    // Signal numbers on Linux.
+    pub static SIGHUP  : c_int = 1;
+    pub static SIGINT  : c_int = 2;
+    pub static SIGQUIT : c_int = 3;
+    pub static SIGKILL : c_int = 9;
+    pub static SIGTERM : c_int = 15;
+    pub static SIGCHLD : c_int = 17;
+    pub static SIGCONT : c_int = 18;
+    pub static SIGSTOP : c_int = 19;
+    pub static SIGTSTP : c_int = 20;
+    pub static SIGTTIN : c_int = 21;
+    pub static SIGTTOU : c_int = 22;
+
+    // Names and numbers of every signal on Linux, as `kill -l` lists them.
+    pub static SIGNALS: &'static [(c_int, &'static str)] = &[
+        (1, "HUP"), (2, "INT"), (3, "QUIT"), (4, "ILL"), (5, "TRAP"),
+        (6, "ABRT"), (7, "BUS"), (8, "FPE"), (9, "KILL"), (10, "USR1"),
+        (11, "SEGV"), (12, "USR2"), (13, "PIPE"), (14, "ALRM"), (15, "TERM"),
+        (16, "STKFLT"), (17, "CHLD"), (18, "CONT"), (19, "STOP"), (20, "TSTP"),
+        (21, "TTIN"), (22, "TTOU"), (23, "URG"), (24, "XCPU"), (25, "XFSZ"),
+        (26, "VTALRM"), (27, "PROF"), (28, "WINCH"), (29, "IO"), (30, "PWR"),
+        (31, "SYS"),
+    ];
+
+    // The name of a signal, without the SIG prefix.
+    pub fn signal_name(sig: c_int) -> Option<&'static str> {
+        SIGNALS.iter().find(|&&(n, _)| n == sig).map(|&(_, name)| name)
+    }
+
+    // The number of a signal given as a number or a name, with or without
+    // the SIG prefix and in any case: 15, TERM, SIGTERM and sigterm are all
+    // the same signal. 0 is accepted too, for checking a process exists.
+    pub fn signal_number(spec: &str) -> Option<c_int> {
+        match from_str::<c_int>(spec) {
+            Some(sig) => {
+                if sig == 0 || signal_name(sig).is_some() { Some(sig) } else { None }
+            }
+            None => {
+                let upper = spec.to_ascii_upper();
+                let name = if upper.starts_with("SIG") {
+                    upper.slice_from(3)
+                }
+                else {
+                    upper.as_slice()
+                };
+                SIGNALS.iter().find(|&&(_, n)| n == name).map(|&(sig, _)| sig)
+            }
+        }
+    }
+
+    // sigset_t from <signal.h> on Linux.
+    struct SigSet {
+        bits: [u64, ..16],
+    }
+
+    static SIG_BLOCK   : c_int = 0;
+    static SIG_SETMASK : c_int = 2;
+
+    extern {
+        pub fn kill(pid: pid_t, sig: c_int) -> c_int;
+        fn signal(sig: c_int, handler: size_t) -> size_t;
+        fn sigemptyset(set: *mut SigSet) -> c_int;
+        fn sigaddset(set: *mut SigSet, sig: c_int) -> c_int;
+        fn pthread_sigmask(how: c_int, set: *SigSet, old: *mut SigSet) -> c_int;
+    }
+
+    static SIG_IGN : size_t = 1;
+
+    extern "C" fn do_nothing(_sig: c_int) {
+    }
+
+    // Signals that have arrived for `trap`, waiting for the shell to get
+    // round to running their commands between commands.
+    static mut PENDING : [bool, ..65] = [false, ..65];
+
+    extern "C" fn note_signal(sig: c_int) {
+        unsafe {
+            PENDING[sig as uint] = true;
+        }
+    }
+
+    // Catch sig with a handler that does nothing. For the shell itself this
+    // is as good as ignoring it, but unlike SIG_IGN it isn't inherited:
+    // exec resets caught signals to their default action in the child.
+    pub fn catch(sig: c_int) {
+        unsafe {
+            signal(sig, do_nothing as size_t);
+        }
+    }
+
+    // Note sig's arrival for take_pending rather than taking its usual
+    // action. Returns the handler it had, for restore.
+    pub fn trap(sig: c_int) -> size_t {
+        unsafe {
+            signal(sig, note_signal as size_t)
+        }
+    }
+
+    // Ignore sig, returning the handler it had.
+    pub fn ignore(sig: c_int) -> size_t {
+        unsafe {
+            signal(sig, SIG_IGN)
+        }
+    }
+
+    // Put back a handler returned by trap or ignore.
+    pub fn restore(sig: c_int, handler: size_t) {
+        unsafe {
+            signal(sig, handler);
+        }
+    }
+
+    // The signals noted since the last call, in order of number.
+    pub fn take_pending() -> ~[c_int] {
+        let mut sigs = ~[];
+        unsafe {
+            for sig in range(1u, PENDING.len()) {
+                if PENDING[sig] {
+                    PENDING[sig] = false;
+                    sigs.push(sig as c_int);
+                }
+            }
+        }
+        sigs
+    }
+
+    // Run f with sig blocked in the calling thread.
+    pub fn with_blocked<T>(sig: c_int, f: || -> T) -> T {
+        unsafe {
+            let mut set = SigSet { bits: [0, ..16] };
+            let mut old = SigSet { bits: [0, ..16] };
+            sigemptyset(&mut set);
+            sigaddset(&mut set, sig);
+            pthread_sigmask(SIG_BLOCK, &set, &mut old);
+            let result = f();
+            pthread_sigmask(SIG_SETMASK, &old, &mut set);
+            result
+        }
+    }
+}
@@ -0,0 +1,133 @@
+#[allow(dead_code)]
+pub mod terminal {
+    // Process groups and the controlling terminal. Every job runs in its own
+    // process group, and whichever group owns the terminal gets the signals
+    // typed at it (Ctrl-C, Ctrl-Z). The shell hands the terminal to a
+    // foreground job while it runs and takes it back afterwards.
+    use std::libc::{c_int, pid_t};
+    use signals::signals::{kill, catch, with_blocked, SIGTSTP, SIGTTIN, SIGTTOU};
+
+    // struct termios from <termios.h> on Linux.
+    pub struct Termios {
+        c_iflag  : u32,
+        c_oflag  : u32,
+        c_cflag  : u32,
+        c_lflag  : u32,
+        c_line   : u8,
+        c_cc     : [u8, ..32],
+        c_ispeed : u32,
+        c_ospeed : u32,
+    }
+
+    impl Clone for Termios {
+        fn clone(&self) -> Termios {
+            Termios {
+                c_iflag: self.c_iflag,
+                c_oflag: self.c_oflag,
+                c_cflag: self.c_cflag,
+                c_lflag: self.c_lflag,
+                c_line: self.c_line,
+                c_cc: self.c_cc,
+                c_ispeed: self.c_ispeed,
+                c_ospeed: self.c_ospeed,
+            }
+        }
+    }
+
+    static TERMINAL  : c_int = 0;
+    static TCSADRAIN : c_int = 1;
+
+    extern {
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, action: c_int, termios: *Termios) -> c_int;
+        fn tcgetpgrp(fd: c_int) -> pid_t;
+        fn tcsetpgrp(fd: c_int, pgrp: pid_t) -> c_int;
+        fn setpgid(pid: pid_t, pgid: pid_t) -> c_int;
+        fn getpgrp() -> pid_t;
+        fn isatty(fd: c_int) -> c_int;
+    }
+
+    // Set the shell up for job control: wait until it is in the foreground,
+    // put it in its own process group and take the terminal. Returns false
+    // (leaving job control off) if stdin isn't a terminal.
+    pub fn init() -> bool {
+        unsafe {
+            if isatty(TERMINAL) == 0 {
+                return false;
+            }
+            loop {
+                let pgrp = getpgrp();
+                if tcgetpgrp(TERMINAL) == pgrp {
+                    break;
+                }
+                kill(-pgrp, SIGTTIN);
+            }
+            catch(SIGTSTP);
+            catch(SIGTTIN);
+            // Fails harmlessly if we're already a group (or session) leader.
+            setpgid(0, 0);
+        }
+        take_back();
+        true
+    }
+
+    // Move pid into the process group pgid. The shell does this after the
+    // spawn, and the child itself before it execs (see join); whichever
+    // comes first wins the race. It's harmless if the child has already
+    // exited.
+    pub fn set_pgid(pid: pid_t, pgid: pid_t) {
+        unsafe {
+            setpgid(pid, pgid);
+        }
+    }
+
+    // Run in a process gash has started, before it becomes the program it
+    // is for: move it into the process group pgid (a group of its own if
+    // pgid is 0), and give that group the terminal if its job is in the
+    // foreground. std::run has no hook between fork and exec, so gash is
+    // started in launch mode to do this.
+    pub fn join(pgid: pid_t, foreground: bool) {
+        unsafe {
+            setpgid(0, pgid);
+        }
+        if foreground {
+            give_to(unsafe { getpgrp() });
+        }
+    }
+
+    // Give the terminal to the process group pgid. SIGTTOU is blocked
+    // rather than ignored, so that children don't inherit the disposition.
+    pub fn give_to(pgid: pid_t) {
+        with_blocked(SIGTTOU, || unsafe { tcsetpgrp(TERMINAL, pgid) });
+    }
+
+    // Take the terminal back for the shell.
+    pub fn take_back() {
+        give_to(unsafe { getpgrp() });
+    }
+
+    // The terminal's current modes.
+    pub fn get_modes() -> Option<Termios> {
+        let mut modes = Termios {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: 0,
+            c_line: 0,
+            c_cc: [0, ..32],
+            c_ispeed: 0,
+            c_ospeed: 0,
+        };
+        if unsafe { tcgetattr(TERMINAL, &mut modes) } == 0 {
+            Some(modes)
+        }
+        else {
+            None
+        }
+    }
+
+    // Restore modes saved with get_modes.
+    pub fn set_modes(modes: &Termios) {
+        with_blocked(SIGTTOU, || unsafe { tcsetattr(TERMINAL, TCSADRAIN, modes) });
+    }
+}
@@ -0,0 +1,303 @@
+#[allow(dead_code)]
+pub mod usage {
+    // Resource usage for the `time` keyword. Each process of a timed
+    // pipeline is asked about as it exits, before the runtime reaps it:
+    // waitid with WNOWAIT leaves the child to be waited for again, but the
+    // kernel still fills in its rusage (its own and its children's). The
+    // pipeline's usage is the sum of its processes', except the maximum
+    // resident set size, which is the largest of any of them. A process
+    // that has already been reaped can't be asked, and counts for nothing.
+    use std::libc::{c_int, c_long, pid_t};
+    use std::libc::consts::os::posix88::EINTR;
+    use std::cmp::{max, min};
+    use std::os;
+
+    // struct timeval and struct rusage from <sys/resource.h> on Linux.
+    struct TimeVal {
+        tv_sec  : c_long,
+        tv_usec : c_long,
+    }
+
+    struct RUsage {
+        ru_utime    : TimeVal,
+        ru_stime    : TimeVal,
+        ru_maxrss   : c_long,
+        ru_ixrss    : c_long,
+        ru_idrss    : c_long,
+        ru_isrss    : c_long,
+        ru_minflt   : c_long,
+        ru_majflt   : c_long,
+        ru_nswap    : c_long,
+        ru_inblock  : c_long,
+        ru_oublock  : c_long,
+        ru_msgsnd   : c_long,
+        ru_msgrcv   : c_long,
+        ru_nsignals : c_long,
+        ru_nvcsw    : c_long,
+        ru_nivcsw   : c_long,
+    }
+
+    // The C library's waitid has no rusage argument, but the system call
+    // does.
+    #[cfg(target_arch = "x86_64")]
+    static SYS_WAITID : c_long = 247;
+    #[cfg(target_arch = "x86")]
+    static SYS_WAITID : c_long = 284;
+    #[cfg(target_arch = "arm")]
+    static SYS_WAITID : c_long = 280;
+
+    static P_PID   : c_int = 1;
+    static WEXITED : c_int = 4;
+    static WNOWAIT : c_int = 0x01000000;
+
+    extern {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    // How a timed pipeline is reported: by $TIMEFORMAT, in the POSIX
+    // format (time -p), or at length (time -v).
+    #[deriving(Eq)]
+    pub enum TimeMode {
+        Formatted,
+        Posix,
+        Verbose,
+    }
+
+    // What the shell's children have used, in microseconds and counts.
+    #[deriving(Clone)]
+    pub struct Usage {
+        user          : i64,
+        system        : i64,
+        max_rss       : i64,
+        major_faults  : i64,
+        minor_faults  : i64,
+        voluntary     : i64,
+        involuntary   : i64,
+    }
+
+    impl Usage {
+        // Nothing used yet.
+        pub fn new() -> Usage {
+            Usage {
+                user: 0,
+                system: 0,
+                max_rss: 0,
+                major_faults: 0,
+                minor_faults: 0,
+                voluntary: 0,
+                involuntary: 0,
+            }
+        }
+
+        // Block until the child pid exits, and return what it used without
+        // reaping it. None if it can't be waited for.
+        pub fn of(pid: pid_t) -> Option<Usage> {
+            let mut info = [0u8, ..128];
+            let mut usage = RUsage {
+                ru_utime: TimeVal { tv_sec: 0, tv_usec: 0 },
+                ru_stime: TimeVal { tv_sec: 0, tv_usec: 0 },
+                ru_maxrss: 0, ru_ixrss: 0, ru_idrss: 0, ru_isrss: 0,
+                ru_minflt: 0, ru_majflt: 0, ru_nswap: 0, ru_inblock: 0,
+                ru_oublock: 0, ru_msgsnd: 0, ru_msgrcv: 0, ru_nsignals: 0,
+                ru_nvcsw: 0, ru_nivcsw: 0,
+            };
+            let mut ret;
+            loop {
+                ret = unsafe {
+                    syscall(SYS_WAITID, P_PID, pid, info.as_mut_ptr(), WEXITED | WNOWAIT,
+                            &mut usage)
+                };
+                if ret == 0 || os::errno() as c_int != EINTR {
+                    break;
+                }
+            }
+            if ret != 0 {
+                return None;
+            }
+            Some(Usage {
+                user: usage.ru_utime.tv_sec as i64 * 1000000 + usage.ru_utime.tv_usec as i64,
+                system: usage.ru_stime.tv_sec as i64 * 1000000 + usage.ru_stime.tv_usec as i64,
+                max_rss: usage.ru_maxrss as i64,
+                major_faults: usage.ru_majflt as i64,
+                minor_faults: usage.ru_minflt as i64,
+                voluntary: usage.ru_nvcsw as i64,
+                involuntary: usage.ru_nivcsw as i64,
+            })
+        }
+
+        // Count what another process of the same pipeline used.
+        pub fn add(&mut self, other: &Usage) {
+            self.user += other.user;
+            self.system += other.system;
+            self.max_rss = max(self.max_rss, other.max_rss);
+            self.major_faults += other.major_faults;
+            self.minor_faults += other.minor_faults;
+            self.voluntary += other.voluntary;
+            self.involuntary += other.involuntary;
+        }
+    }
+
+    // bash's default $TIMEFORMAT.
+    pub static DEFAULT_FORMAT : &'static str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+    static POSIX_FORMAT : &'static str = "real %2R\nuser %2U\nsys %2S";
+
+    // The report for a pipeline that took real microseconds and used
+    // usage. format is $TIMEFORMAT, which the Formatted mode follows.
+    pub fn report(mode: TimeMode, format: &str, cmd_line: &str, real: i64, usage: &Usage) -> ~str {
+        match mode {
+            Formatted => { expand_format(format, real, usage) }
+            Posix     => { expand_format(POSIX_FORMAT, real, usage) }
+            Verbose   => { verbose(cmd_line, real, usage) }
+        }
+    }
+
+    // Expand a $TIMEFORMAT string. %R, %U and %S are the real, user and
+    // system time in seconds, and %P the CPU percentage. An optional digit
+    // between % and the letter is the number of decimal places (3 at
+    // most), and an l gives the long form, e.g. 1m2.345s.
+    fn expand_format(format: &str, real: i64, usage: &Usage) -> ~str {
+        let mut out = ~"";
+        let mut chars = format.chars().peekable();
+        loop {
+            let c = match chars.next() {
+                Some(c) => { c }
+                None    => { return out; }
+            };
+            if c != '%' {
+                out.push_char(c);
+                continue;
+            }
+            let mut places = 3;
+            let mut long = false;
+            match chars.peek() {
+                Some(&d) if d.is_digit() => {
+                    places = min(d as uint - '0' as uint, 3);
+                    chars.next();
+                }
+                _ => { }
+            }
+            match chars.peek() {
+                Some(&'l') => {
+                    long = true;
+                    chars.next();
+                }
+                _ => { }
+            }
+            match chars.next() {
+                Some('%') => { out.push_char('%'); }
+                Some('R') => { out.push_str(seconds(real, places, long)); }
+                Some('U') => { out.push_str(seconds(usage.user, places, long)); }
+                Some('S') => { out.push_str(seconds(usage.system, places, long)); }
+                Some('P') => { out.push_str(format!("{:.2f}", percent(real, usage))); }
+                Some(other) => {
+                    out.push_char('%');
+                    out.push_char(other);
+                }
+                None => {
+                    out.push_char('%');
+                }
+            }
+        }
+    }
+
+    // Microseconds as seconds with the given decimal places, or in the long
+    // form as minutes and seconds.
+    fn seconds(micros: i64, places: uint, long: bool) -> ~str {
+        let secs = micros as f64 / 1000000.0;
+        if long {
+            let minutes = (secs / 60.0).floor();
+            format!("{}m{}s", minutes as i64, fixed(secs - minutes * 60.0, places))
+        }
+        else {
+            fixed(secs, places)
+        }
+    }
+
+    fn fixed(value: f64, places: uint) -> ~str {
+        match places {
+            0 => { format!("{:.0f}", value) }
+            1 => { format!("{:.1f}", value) }
+            2 => { format!("{:.2f}", value) }
+            _ => { format!("{:.3f}", value) }
+        }
+    }
+
+    fn percent(real: i64, usage: &Usage) -> f64 {
+        if real <= 0 {
+            0.0
+        }
+        else {
+            (usage.user + usage.system) as f64 * 100.0 / real as f64
+        }
+    }
+
+    // The report of `time -v`, laid out like GNU time's.
+    fn verbose(cmd_line: &str, real: i64, usage: &Usage) -> ~str {
+        let elapsed = real as f64 / 1000000.0;
+        let minutes = (elapsed / 60.0).floor();
+        let lines = [
+            format!("Command being timed: \"{:s}\"", cmd_line),
+            format!("User time (seconds): {:.2f}", usage.user as f64 / 1000000.0),
+            format!("System time (seconds): {:.2f}", usage.system as f64 / 1000000.0),
+            format!("Percent of CPU this job got: {:.0f}%", percent(real, usage)),
+            format!("Elapsed (wall clock) time (h:mm:ss or m:ss): {}:{:05.2f}",
+                    minutes as i64, elapsed - minutes * 60.0),
+            format!("Maximum resident set size (kbytes): {}", usage.max_rss),
+            format!("Major (requiring I/O) page faults: {}", usage.major_faults),
+            format!("Minor (reclaiming a frame) page faults: {}", usage.minor_faults),
+            format!("Voluntary context switches: {}", usage.voluntary),
+            format!("Involuntary context switches: {}", usage.involuntary),
+        ];
+        let lines: ~[~str] = lines.iter().map(|line| format!("\t{:s}", *line)).collect();
+        lines.connect("\n")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{Usage, expand_format, report, Posix};
+
+        // 1.5s of user time and 0.25s of system time.
+        fn usage() -> Usage {
+            let mut usage = Usage::new();
+            usage.user = 1500000;
+            usage.system = 250000;
+            usage
+        }
+
+        #[test]
+        fn times_default_to_three_places() {
+            assert_eq!(expand_format("%R %U %S", 62345678, &usage()), ~"62.346 1.500 0.250");
+        }
+
+        #[test]
+        fn digits_set_the_places_up_to_three() {
+            assert_eq!(expand_format("%0R", 62345678, &usage()), ~"62");
+            assert_eq!(expand_format("%1U", 62345678, &usage()), ~"1.5");
+            assert_eq!(expand_format("%2S", 62345678, &usage()), ~"0.25");
+            assert_eq!(expand_format("%9R", 62345678, &usage()), ~"62.346");
+        }
+
+        #[test]
+        fn long_form_has_minutes() {
+            assert_eq!(expand_format("%3lR", 62345678, &usage()), ~"1m2.346s");
+            assert_eq!(expand_format("%lU", 62345678, &usage()), ~"0m1.500s");
+        }
+
+        #[test]
+        fn percent_is_cpu_time_over_real_time() {
+            assert_eq!(expand_format("%P", 2000000, &usage()), ~"87.50");
+            assert_eq!(expand_format("%P", 0, &usage()), ~"0.00");
+        }
+
+        #[test]
+        fn other_text_is_copied() {
+            assert_eq!(expand_format("took %%%x\t%", 0, &usage()), ~"took %%x\t%");
+        }
+
+        #[test]
+        fn posix_format_has_two_places() {
+            assert_eq!(report(Posix, "", "true", 62345678, &usage()),
+                       ~"real 62.35\nuser 1.50\nsys 0.25");
+        }
+    }
+}